@@ -0,0 +1,100 @@
+//! Generates an enum for each of the lists of entity codes in the entities directory.
+//!
+//! Each list contains one entity code per line. Lines starting with # are ignored.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The enum to generate for each list of entity codes.
+const ENTITY_LISTS: [(&str, &str); 7] = [
+    ("PartOfSpeech", "pos"),
+    ("KanjiInfo", "ke_inf"),
+    ("ReadingInfo", "re_inf"),
+    ("Field", "field"),
+    ("Misc", "misc"),
+    ("Dialect", "dial"),
+    ("NameType", "name_type"),
+];
+
+fn main() {
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("entities.rs");
+    let mut out = File::create(&out_path).unwrap();
+
+    for &(enum_name, list_name) in ENTITY_LISTS.iter() {
+        let list_path = format!("entities/{}.txt", list_name);
+        println!("cargo:rerun-if-changed={}", list_path);
+
+        let mut list = String::new();
+        File::open(&list_path)
+            .and_then(|mut file| file.read_to_string(&mut list))
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", list_path, e));
+        let codes: Vec<&str> = list.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        write_enum(&mut out, enum_name, list_name, &codes);
+    }
+}
+
+fn write_enum<W: Write>(out: &mut W, enum_name: &str, list_name: &str, codes: &[&str]) {
+    let variants: Vec<String> = codes.iter().map(|code| variant_name(code)).collect();
+    let mut seen = HashSet::new();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        if !seen.insert(variant) {
+            panic!("Entity code {} in {} produces a duplicate variant {}", code, list_name, variant);
+        }
+    }
+
+    writeln!(out, "/// Entity codes used in <{}> elements", list_name).unwrap();
+    writeln!(out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]").unwrap();
+    writeln!(out, "pub enum {} {{", enum_name).unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(out, "    /// &{};", code).unwrap();
+        writeln!(out, "    {},", variant).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl Entity for {} {{", enum_name).unwrap();
+    writeln!(out, "    fn from_code(code: &str) -> Option<{}> {{", enum_name).unwrap();
+    writeln!(out, "        match code {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(out, "            {:?} => Some({}::{}),", code, enum_name, variant).unwrap();
+    }
+    writeln!(out, "            _ => None,").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+    writeln!(out, "    fn code(&self) -> &'static str {{").unwrap();
+    writeln!(out, "        match *self {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(out, "            {}::{} => {:?},", enum_name, variant, code).unwrap();
+    }
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl ::serde::Serialize for {} {{", enum_name).unwrap();
+    writeln!(
+        out,
+        "    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {{"
+    ).unwrap();
+    writeln!(out, "        serializer.serialize_str(self.code())").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+}
+
+/// Convert an entity code like "v5k-s" into a variant name like "V5kS".
+fn variant_name(code: &str) -> String {
+    code.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
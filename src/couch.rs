@@ -0,0 +1,766 @@
+use failure::{Error, ResultExt};
+use serde_json;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use ureq;
+
+use jmdict::dtd::EntityTable;
+use jmdict::kanjidic::Character;
+use jmdict::{Entry, NameEntry, ReleaseInfo};
+
+/// The number of documents to send in each _bulk_docs request.
+///
+/// Each JMDict entry serializes to somewhere around 1KB so this keeps each request to a manageable
+/// size while still avoiding a round trip per entry.
+const BATCH_SIZE: usize = 1000;
+
+/// The ID of the document storing the entity definitions from the DOCTYPE.
+const ENTITIES_DOC_ID: &str = "entities";
+
+/// The ID of the document describing the release the database was last synced from.
+const METADATA_DOC_ID: &str = "metadata";
+
+/// The number of documents added, modified and removed by a sync.
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncStats {
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+}
+
+/// What we need to know about a document already in the database in order to decide if it needs
+/// updating.
+///
+/// We keep a hash of the content rather than the document itself since holding the whole of
+/// JMDict in memory as JSON values would be very wasteful.
+struct ExistingDoc {
+    rev: String,
+    hash: u64,
+}
+
+/// A single CouchDB database accessed over HTTP.
+pub struct Database {
+    agent: ureq::Agent,
+    /// The URL of the database itself, e.g. http://localhost:5984/jmdict
+    url: String,
+}
+
+impl Database {
+    pub fn new(couch_url: &str, name: &str) -> Database {
+        Database {
+            agent: ureq::AgentBuilder::new().build(),
+            url: format!("{}/{}", couch_url.trim_end_matches('/'), name),
+        }
+    }
+
+    /// Create the database unless it already exists.
+    pub fn create_if_missing(&self) -> Result<(), Error> {
+        match self.agent.get(&self.url).call() {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(404, _)) => (),
+            Err(e) => bail!("Could not connect to {}: {}", self.url, e),
+        }
+
+        match self.agent.put(&self.url).call() {
+            Ok(_) => Ok(()),
+            // Someone else created it in the meantime
+            Err(ureq::Error::Status(412, _)) => Ok(()),
+            Err(e) => bail!("Could not create database {}: {}", self.url, e),
+        }
+    }
+
+    /// Fetch a single document, returning None if it does not exist.
+    fn get_doc(&self, id: &str) -> Result<Option<Value>, Error> {
+        match self.agent.get(&format!("{}/{}", self.url, id)).call() {
+            Ok(response) => Ok(Some(response
+                .into_json()
+                .context("Failed to read document")?)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => bail!("Failed to fetch document {}: {}", id, e),
+        }
+    }
+
+    /// Write a single document unless the existing document has the same content, returning true
+    /// if the document was written.
+    pub fn update_doc(&self, mut doc: Value) -> Result<bool, Error> {
+        let id = match doc["_id"].as_str() {
+            Some(id) => id.to_owned(),
+            None => bail!("Document is missing an _id: {}", doc),
+        };
+
+        if let Some(mut current) = self.get_doc(&id)? {
+            let rev = match current.as_object_mut().and_then(|current| current.remove("_rev")) {
+                Some(rev) => rev,
+                None => bail!("Document {} has no _rev", id),
+            };
+            if current == doc {
+                return Ok(false);
+            }
+            doc["_rev"] = rev;
+        }
+
+        self.bulk_docs(&[doc])?;
+        Ok(true)
+    }
+
+    /// Fetch the ID and revision of every document whose ID starts with `prefix`.
+    pub fn revs_with_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let response: Value = self.agent
+            .get(&format!("{}/_all_docs", self.url))
+            .query("startkey", &json!(prefix).to_string())
+            .query("endkey", &json!(format!("{}\u{fff0}", prefix)).to_string())
+            .call()
+            .context("Failed to fetch existing documents")?
+            .into_json()
+            .context("Failed to read _all_docs response")?;
+
+        let rows = match response["rows"].as_array() {
+            Some(rows) => rows,
+            None => bail!("Unexpected _all_docs response: {}", response),
+        };
+        rows.iter()
+            .map(|row| match (row["id"].as_str(), row["value"]["rev"].as_str()) {
+                (Some(id), Some(rev)) => Ok((id.to_owned(), rev.to_owned())),
+                _ => bail!("Unexpected _all_docs row: {}", row),
+            })
+            .collect()
+    }
+
+    /// Fetch the revision and content hash of every document in the database except for design
+    /// documents.
+    ///
+    /// The documents are fetched in pages of BATCH_SIZE using the startkey of the next page so
+    /// that we never have to hold the response for the whole database in memory.
+    fn existing_docs(&self) -> Result<HashMap<String, ExistingDoc>, Error> {
+        let mut docs = HashMap::new();
+        let mut start_key: Option<String> = None;
+
+        loop {
+            let mut request = self.agent
+                .get(&format!("{}/_all_docs", self.url))
+                .query("include_docs", "true")
+                .query("limit", &(BATCH_SIZE + 1).to_string());
+            if let Some(ref key) = start_key {
+                request = request.query("startkey", &json!(key).to_string());
+            }
+            let response: Value = request
+                .call()
+                .context("Failed to fetch existing documents")?
+                .into_json()
+                .context("Failed to read _all_docs response")?;
+
+            let rows = match response["rows"].as_array() {
+                Some(rows) => rows,
+                None => bail!("Unexpected _all_docs response: {}", response),
+            };
+
+            for row in rows.iter().take(BATCH_SIZE) {
+                let mut doc = row["doc"].clone();
+                let (id, rev) = match (doc["_id"].as_str(), doc["_rev"].as_str()) {
+                    (Some(id), Some(rev)) => (id.to_owned(), rev.to_owned()),
+                    _ => bail!("Unexpected _all_docs row: {}", row),
+                };
+                if id.starts_with("_design/") {
+                    continue;
+                }
+                if let Some(doc) = doc.as_object_mut() {
+                    doc.remove("_rev");
+                }
+                docs.insert(
+                    id,
+                    ExistingDoc {
+                        rev,
+                        hash: content_hash(&doc),
+                    },
+                );
+            }
+
+            if rows.len() <= BATCH_SIZE {
+                break;
+            }
+            start_key = rows[BATCH_SIZE]["id"].as_str().map(str::to_owned);
+        }
+
+        Ok(docs)
+    }
+
+    /// Write the supplied documents using the _bulk_docs API, failing if any of the documents
+    /// could not be written.
+    pub fn bulk_docs(&self, docs: &[Value]) -> Result<(), Error> {
+        let response: Value = self.agent
+            .post(&format!("{}/_bulk_docs", self.url))
+            .send_json(json!({ "docs": docs }))
+            .context("Failed to post documents")?
+            .into_json()
+            .context("Failed to read _bulk_docs response")?;
+
+        let results = match response.as_array() {
+            Some(results) => results,
+            None => bail!("Unexpected _bulk_docs response: {}", response),
+        };
+        for result in results {
+            if let Some(error) = result.get("error") {
+                bail!(
+                    "Failed to write document {}: {} ({})",
+                    result["id"],
+                    error,
+                    result["reason"]
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Update the database so that its entry documents match the supplied entries, writing only the
+/// documents that have changed.
+pub fn sync_entries(db: &Database, entries: &[Entry]) -> Result<SyncStats, Error> {
+    let docs = entries.iter().map(entry_doc);
+    sync_docs(db, docs, is_entry_id)
+}
+
+/// As with `sync_entries` but writing the documents produced by `language_docs` so that each
+/// entry is split into a shared document and one document per language.
+pub fn sync_entries_by_language(db: &Database, entries: &[Entry]) -> Result<SyncStats, Error> {
+    let docs = entries
+        .iter()
+        .flat_map(|entry| match language_docs(entry) {
+            Ok(docs) => docs.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        });
+    sync_docs(db, docs, is_entry_id)
+}
+
+/// Make the database's name documents match the supplied JMnedict entries.
+pub fn sync_names(db: &Database, names: &[NameEntry]) -> Result<SyncStats, Error> {
+    let docs = names.iter().map(name_doc);
+    sync_docs(db, docs, is_entry_id)
+}
+
+/// Make the database's character documents match the supplied KANJIDIC2 characters.
+pub fn sync_characters(db: &Database, characters: &[Character]) -> Result<SyncStats, Error> {
+    let docs = characters.iter().map(character_doc);
+    sync_docs(db, docs, is_character_id)
+}
+
+/// Update the database so that the documents for which `is_managed` returns true match `docs`.
+///
+/// New documents are inserted, documents whose content differs are updated using their current
+/// revision, and managed documents that do not appear in `docs` are deleted. Documents that
+/// `is_managed` does not match (e.g. metadata documents) are never deleted.
+///
+/// Fails without changing anything if `docs` is empty but the database has managed documents
+/// since that almost certainly means the input was truncated or filtered down to nothing.
+pub fn sync_docs<I, F>(db: &Database, docs: I, is_managed: F) -> Result<SyncStats, Error>
+where
+    I: Iterator<Item = Result<Value, Error>>,
+    F: Fn(&str) -> bool,
+{
+    let mut existing = db.existing_docs()?;
+    let mut stats = SyncStats::default();
+    let mut pending: Vec<Value> = Vec::new();
+    let mut count = 0;
+
+    for doc in docs {
+        let mut doc = doc?;
+        count += 1;
+        let id = match doc["_id"].as_str() {
+            Some(id) => id.to_owned(),
+            None => bail!("Document is missing an _id: {}", doc),
+        };
+
+        match existing.remove(&id) {
+            Some(ref current) if current.hash == content_hash(&doc) => continue,
+            Some(current) => {
+                doc["_rev"] = Value::String(current.rev);
+                stats.modified += 1;
+            }
+            None => stats.added += 1,
+        }
+
+        pending.push(doc);
+        if pending.len() >= BATCH_SIZE {
+            db.bulk_docs(&pending)?;
+            pending.clear();
+        }
+    }
+
+    let mut removed: Vec<(String, ExistingDoc)> = existing
+        .into_iter()
+        .filter(|(id, _)| is_managed(id))
+        .collect();
+    ensure!(
+        count > 0 || removed.is_empty(),
+        "Refusing to remove every existing document ({}) since there is nothing to sync",
+        removed.len()
+    );
+    removed.sort_by(|a, b| a.0.cmp(&b.0));
+    for (id, current) in removed {
+        pending.push(json!({ "_id": id, "_rev": current.rev, "_deleted": true }));
+        stats.removed += 1;
+        if pending.len() >= BATCH_SIZE {
+            db.bulk_docs(&pending)?;
+            pending.clear();
+        }
+    }
+
+    if !pending.is_empty() {
+        db.bulk_docs(&pending)?;
+    }
+
+    Ok(stats)
+}
+
+/// Store the entity definitions so that clients can look up the descriptions that correspond to
+/// the release we synced from. Returns true if the definitions changed.
+pub fn sync_entities(db: &Database, entities: &EntityTable) -> Result<bool, Error> {
+    db.update_doc(json!({ "_id": ENTITIES_DOC_ID, "entities": entities }))
+}
+
+/// Fail if the database was last synced from a release newer than `release`.
+pub fn check_release(db: &Database, release: &ReleaseInfo) -> Result<(), Error> {
+    let current: ReleaseInfo = match db.get_doc(METADATA_DOC_ID)? {
+        Some(doc) => serde_json::from_value(doc["release"].clone())
+            .context("Failed to read metadata document")?,
+        None => return Ok(()),
+    };
+
+    if let (true, Some(created), Some(current_created)) = (
+        release.is_older_than(&current),
+        release.created.as_ref(),
+        current.created.as_ref(),
+    ) {
+        bail!(
+            "The database was synced from the {} release which is newer than the {} release \
+             being synced",
+            current_created,
+            created
+        );
+    }
+    Ok(())
+}
+
+/// Record the release the database was synced from so that clients can tell which release they
+/// are looking at. Returns true if the release changed.
+pub fn sync_release(db: &Database, release: &ReleaseInfo) -> Result<bool, Error> {
+    db.update_doc(json!({ "_id": METADATA_DOC_ID, "release": release }))
+}
+
+/// Entry documents are keyed by their ent_seq, or their ent_seq and language (e.g. 1000000-ger)
+/// when split by language.
+fn is_entry_id(id: &str) -> bool {
+    let mut parts = id.splitn(2, '-');
+    let ent_seq = parts.next().unwrap_or("");
+    let lang_is_valid = match parts.next() {
+        Some(lang) => !lang.is_empty() && lang.bytes().all(|b| b.is_ascii_lowercase()),
+        None => true,
+    };
+    !ent_seq.is_empty() && ent_seq.bytes().all(|b| b.is_ascii_digit()) && lang_is_valid
+}
+
+/// Character documents are keyed by the character itself.
+fn is_character_id(id: &str) -> bool {
+    id.chars().count() == 1
+}
+
+/// Produce a hash of a document's content.
+///
+/// serde_json keeps object keys sorted so the serialized form is canonical.
+fn content_hash(doc: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    doc.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Serialize an entry as a CouchDB document keyed by its ent_seq.
+pub fn entry_doc(entry: &Entry) -> Result<Value, Error> {
+    let mut doc = serde_json::to_value(entry).context("Failed to serialize entry")?;
+    doc["_id"] = Value::String(entry.id.to_string());
+    doc["priority_score"] = json!(entry.priority_score());
+    doc["search_keys"] = json!(entry.search_keys());
+    Ok(doc)
+}
+
+/// Serialize a JMnedict entry as a CouchDB document keyed by its ent_seq.
+pub fn name_doc(name: &NameEntry) -> Result<Value, Error> {
+    let mut doc = serde_json::to_value(name).context("Failed to serialize name")?;
+    doc["_id"] = Value::String(name.id.to_string());
+    Ok(doc)
+}
+
+/// Serialize a KANJIDIC2 character as a CouchDB document keyed by its literal.
+pub fn character_doc(character: &Character) -> Result<Value, Error> {
+    let mut doc = serde_json::to_value(character).context("Failed to serialize character")?;
+    doc["_id"] = Value::String(character.literal.clone());
+    Ok(doc)
+}
+
+/// Split an entry into a shared document and a document for each language.
+///
+/// The shared document is keyed by the ent_seq and has the same content as the `entry_doc`
+/// except that the senses are replaced by a list of the languages they use. Each language
+/// document is keyed by the ent_seq and language (e.g. 1000000-ger) and contains only the senses
+/// for that language so that sense numbering, e.g. as used by cross-references, is local to that
+/// language. Language documents also include the ent_seq (`entry`), language (`lang`) and
+/// `priority_score` of the entry so that they can be indexed independently.
+pub fn language_docs(entry: &Entry) -> Result<Vec<Value>, Error> {
+    let mut shared = entry_doc(entry)?;
+    if let Some(shared) = shared.as_object_mut() {
+        shared.remove("senses");
+    }
+
+    let mut languages: Vec<&str> = Vec::new();
+    for sense in &entry.senses {
+        if !languages.contains(&sense.language()) {
+            languages.push(sense.language());
+        }
+    }
+    shared["languages"] = json!(languages);
+
+    let mut docs = vec![shared];
+    for lang in languages {
+        let senses: Vec<_> = entry
+            .senses
+            .iter()
+            .filter(|sense| sense.language() == lang)
+            .collect();
+        docs.push(json!({
+            "_id": format!("{}-{}", entry.id, lang),
+            "entry": entry.id,
+            "lang": lang,
+            "priority_score": entry.priority_score(),
+            "senses": serde_json::to_value(senses).context("Failed to serialize senses")?,
+        }));
+    }
+
+    Ok(docs)
+}
+
+#[test]
+fn test_is_entry_id() {
+    assert!(is_entry_id("1000000"));
+    assert!(is_entry_id("1000000-ger"));
+    assert!(!is_entry_id("1000000-"));
+    assert!(!is_entry_id("-ger"));
+    assert!(!is_entry_id("entities"));
+    assert!(!is_entry_id("_design/lang-eng"));
+}
+
+#[test]
+fn test_language_docs() {
+    use jmdict::EntryReader;
+
+    // The second entry in the sample, 〃, has English, German, Russian and Spanish senses.
+    let entry = EntryReader::from_file("data/sample.xml")
+        .unwrap()
+        .nth(1)
+        .unwrap()
+        .unwrap();
+    let docs = language_docs(&entry).unwrap();
+    assert_eq!(docs.len(), 5);
+    assert_eq!(docs[0]["_id"], "1000040");
+    assert_eq!(docs[0]["kanji_entries"][0]["kanji"], "〃");
+    assert_eq!(docs[0]["languages"], json!(["eng", "ger", "rus", "spa"]));
+    assert!(docs[0].get("senses").is_none());
+    assert_eq!(docs[2]["_id"], "1000040-ger");
+    assert_eq!(docs[2]["entry"], 1_000_040);
+    assert_eq!(docs[2]["lang"], "ger");
+    assert_eq!(docs[2]["senses"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_sync_entries_by_language() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+
+    let entries = vec![test_entry(1_000_000, "テスト")];
+    sync_entries(&db, &entries).unwrap();
+
+    // Switching to per-language documents should update the entry document and add the
+    // language document
+    assert_eq!(
+        sync_entries_by_language(&db, &entries).unwrap(),
+        SyncStats {
+            added: 1,
+            modified: 1,
+            removed: 0,
+        }
+    );
+    assert_eq!(server.doc("jmdict", "1000000-eng").unwrap()["entry"], 1_000_000);
+
+    // ... and switching back should remove it
+    assert_eq!(
+        sync_entries(&db, &entries).unwrap(),
+        SyncStats {
+            added: 0,
+            modified: 1,
+            removed: 1,
+        }
+    );
+}
+
+#[cfg(test)]
+fn test_entry(id: u32, kana: &str) -> Entry {
+    use jmdict::{Gloss, ReadingEntry, Sense};
+    Entry {
+        id,
+        kanji_entries: vec![],
+        reading_entries: vec![ReadingEntry {
+            kana: kana.to_owned(),
+            no_kanji: false,
+            related_kanji: vec![],
+            info: Default::default(),
+            priority: Default::default(),
+        }],
+        senses: vec![Sense {
+            only_kanji: vec![],
+            only_readings: vec![],
+            part_of_speech: vec![],
+            cross_refs: vec![],
+            antonyms: vec![],
+            field: vec![],
+            misc: vec![],
+            sense_info: vec![],
+            lang_sources: vec![],
+            dialect: vec![],
+            glosses: vec![Gloss::new("test")],
+            lang: None,
+        }],
+    }
+}
+
+#[test]
+fn test_sync_entries() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+    // Creating an existing database should succeed too
+    db.create_if_missing().unwrap();
+
+    let mut entries: Vec<Entry> = (0..BATCH_SIZE as u32 + 5)
+        .map(|i| test_entry(1_000_000 + i, "テスト"))
+        .collect();
+    assert_eq!(
+        sync_entries(&db, &entries).unwrap(),
+        SyncStats {
+            added: BATCH_SIZE + 5,
+            modified: 0,
+            removed: 0,
+        }
+    );
+    assert_eq!(server.bulk_requests(), 2);
+    assert_eq!(server.doc_count("jmdict"), BATCH_SIZE + 5);
+
+    let doc = server.doc("jmdict", "1000003").unwrap();
+    assert_eq!(doc["id"], 1_000_003);
+    assert_eq!(doc["reading_entries"][0]["kana"], "テスト");
+    assert_eq!(doc["senses"][0]["glosses"][0]["text"], "test");
+    assert_eq!(doc["priority_score"], 0);
+    assert_eq!(doc["search_keys"], json!(["tesuto", "てすと"]));
+
+    // Syncing the same entries again should not write anything
+    assert_eq!(sync_entries(&db, &entries).unwrap(), SyncStats::default());
+    assert_eq!(server.bulk_requests(), 2);
+
+    // Modify one, remove two, and add one
+    entries[3] = test_entry(1_000_003, "テキスト");
+    entries.remove(0);
+    entries.remove(0);
+    entries.push(test_entry(2_000_000, "テスト"));
+    assert_eq!(
+        sync_entries(&db, &entries).unwrap(),
+        SyncStats {
+            added: 1,
+            modified: 1,
+            removed: 2,
+        }
+    );
+    assert_eq!(server.bulk_requests(), 3);
+    assert_eq!(server.doc_count("jmdict"), BATCH_SIZE + 4);
+    assert!(server.doc("jmdict", "1000000").is_none());
+    assert!(server.doc("jmdict", "2000000").is_some());
+
+    let doc = server.doc("jmdict", "1000003").unwrap();
+    assert_eq!(doc["reading_entries"][0]["kana"], "テキスト");
+    assert_eq!(doc["_rev"], "2-test");
+}
+
+#[test]
+fn test_sync_ignores_unmanaged_docs() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+    db.bulk_docs(&[
+        json!({ "_id": "_design/test" }),
+        json!({ "_id": "metadata" }),
+        json!({ "_id": "1000000" }),
+    ]).unwrap();
+
+    assert_eq!(
+        sync_entries(&db, &[test_entry(1_000_010, "あ")]).unwrap(),
+        SyncStats {
+            added: 1,
+            modified: 0,
+            removed: 1,
+        }
+    );
+    assert!(server.doc("jmdict", "_design/test").is_some());
+    assert!(server.doc("jmdict", "metadata").is_some());
+}
+
+#[test]
+fn test_sync_refuses_to_remove_everything() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+
+    // Syncing nothing to an empty database is fine
+    assert_eq!(sync_entries(&db, &[]).unwrap(), SyncStats::default());
+
+    sync_entries(&db, &[test_entry(1_000_000, "ヽ")]).unwrap();
+    assert_eq!(
+        sync_entries(&db, &[]).unwrap_err().to_string(),
+        "Refusing to remove every existing document (1) since there is nothing to sync"
+    );
+    assert!(server.doc("jmdict", "1000000").is_some());
+}
+
+#[test]
+fn test_sync_entities() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+
+    let mut entities = EntityTable::new();
+    entities.insert("adj-i".to_owned(), "adjective (keiyoushi)".to_owned());
+    assert!(sync_entities(&db, &entities).unwrap());
+    assert!(!sync_entities(&db, &entities).unwrap());
+
+    entities.insert("adj-na".to_owned(), "adjectival nouns".to_owned());
+    assert!(sync_entities(&db, &entities).unwrap());
+
+    let doc = server.doc("jmdict", ENTITIES_DOC_ID).unwrap();
+    assert_eq!(doc["entities"]["adj-i"], "adjective (keiyoushi)");
+    assert_eq!(doc["entities"]["adj-na"], "adjectival nouns");
+    assert_eq!(doc["_rev"], "2-test");
+
+    // Syncing entries should leave the entities document alone
+    sync_entries(&db, &[]).unwrap();
+    assert!(server.doc("jmdict", ENTITIES_DOC_ID).is_some());
+}
+
+#[test]
+fn test_sync_release() {
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+
+    let release = ReleaseInfo {
+        created: Some("2018-02-14".to_owned()),
+        dtd_revisions: vec!["1.08".to_owned()],
+        input_hash: "abc".to_owned(),
+        entry_count: 13,
+    };
+    check_release(&db, &release).unwrap();
+    assert!(sync_release(&db, &release).unwrap());
+    assert!(!sync_release(&db, &release).unwrap());
+    let doc = server.doc("jmdict", METADATA_DOC_ID).unwrap();
+    assert_eq!(doc["release"]["created"], "2018-02-14");
+    assert_eq!(doc["release"]["entry_count"], 13);
+
+    let older = ReleaseInfo {
+        created: Some("2018-01-01".to_owned()),
+        ..release.clone()
+    };
+    assert_eq!(
+        check_release(&db, &older).unwrap_err().to_string(),
+        "The database was synced from the 2018-02-14 release which is newer than the 2018-01-01 \
+         release being synced"
+    );
+    let newer = ReleaseInfo {
+        created: Some("2018-03-01".to_owned()),
+        ..release
+    };
+    check_release(&db, &newer).unwrap();
+}
+
+#[test]
+fn test_sync_characters() {
+    use jmdict::kanjidic::CharacterReader;
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "kanjidic");
+    db.create_if_missing().unwrap();
+
+    let mut characters: Vec<Character> = CharacterReader::from_file("data/kanjidic2-sample.xml")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let stats = sync_characters(&db, &characters).unwrap();
+    assert_eq!(stats.added, 4);
+    let doc = server.doc("kanjidic", "亜").unwrap();
+    assert_eq!(doc["stroke_count"], 7);
+    assert_eq!(doc["readings"]["ja_on"][0], "ア");
+
+    assert_eq!(sync_characters(&db, &characters).unwrap(), SyncStats::default());
+
+    characters[0].jlpt = Some(2);
+    characters.pop();
+    let stats = sync_characters(&db, &characters).unwrap();
+    assert_eq!(
+        stats,
+        SyncStats {
+            added: 0,
+            modified: 1,
+            removed: 1,
+        }
+    );
+    assert!(server.doc("kanjidic", "々").is_none());
+}
+
+#[test]
+fn test_sync_names() {
+    use jmdict::NameReader;
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmnedict");
+    db.create_if_missing().unwrap();
+
+    let mut names: Vec<NameEntry> = NameReader::from_file("data/jmnedict-sample.xml")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(sync_names(&db, &names).unwrap().added, 5);
+    let doc = server.doc("jmnedict", "5741815").unwrap();
+    assert_eq!(doc["translations"][0]["name_type"][0], "place");
+    assert_eq!(doc["translations"][0]["details"][0], "Tokyo");
+    assert_eq!(doc["translations"][1]["lang"], "ger");
+
+    names.truncate(4);
+    assert_eq!(
+        sync_names(&db, &names).unwrap(),
+        SyncStats {
+            added: 0,
+            modified: 0,
+            removed: 1,
+        }
+    );
+}
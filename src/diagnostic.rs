@@ -0,0 +1,235 @@
+//! Problems in the JMDict data that don't prevent the affected entries from being read.
+//!
+//! `EntryReader` passes each diagnostic to a `DiagnosticSink` as it is found. Warnings, such as
+//! unrecognized elements, are always reported this way. By default `EntryReader` fails on the
+//! errors detected while parsing but when `EntryReader::report_errors` is used it reports them,
+//! along with the consistency checks performed by `check_entry`, and carries on.
+//!
+//! The cross-references that `resolve_cross_refs` could not resolve can be converted into
+//! diagnostics too. These are only found once every entry has been read so they have no position.
+
+use entry::Entry;
+use failure::Error;
+use input::Position;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// An element we don't know how to handle
+    UnknownElement,
+    /// Text in an element that is only expected to contain other elements
+    UnexpectedText,
+    /// A keb or reb with no text
+    EmptyKey,
+    /// A keb or reb with leading or trailing whitespace
+    KeyWhitespace,
+    /// Glosses with different xml:lang values in the same sense
+    MixedGlossLanguages,
+    /// A re_restr that does not match any keb of the entry
+    MissingRestrictedKanji,
+    /// A stagk that does not match any keb of the entry
+    MissingSenseKanji,
+    /// A stagr that does not match any reb of the entry
+    MissingSenseReading,
+    /// An ent_seq that has already been used by an earlier entry
+    DuplicateId,
+    /// A priority code, entity, cross-reference or attribute value we don't recognize
+    InvalidValue,
+    /// An xref or ant that does not refer to exactly one entry, or to a sense it has
+    UnresolvedCrossReference,
+}
+
+impl DiagnosticKind {
+    pub fn severity(&self) -> Severity {
+        match *self {
+            DiagnosticKind::UnknownElement
+            | DiagnosticKind::UnexpectedText
+            | DiagnosticKind::UnresolvedCrossReference => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// The ent_seq of the affected entry, if it was parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<u32>,
+    /// Where the problem was found, if it was found while reading the input
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        kind: DiagnosticKind,
+        message: String,
+        entry: Option<u32>,
+        position: Position,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity: kind.severity(),
+            kind,
+            message,
+            entry,
+            position: Some(position),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(entry) = self.entry {
+            write!(f, " in entry {}", entry)?;
+        }
+        if let Some(position) = self.position {
+            write!(f, " at {}", position)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that the restrictions within `entry` refer to kanji and readings it actually has.
+///
+/// The diagnostics are reported at `position`, the start of the entry.
+pub fn check_entry(entry: &Entry, position: Position) -> Vec<Diagnostic> {
+    let kanji: HashSet<&str> = entry
+        .kanji_entries
+        .iter()
+        .map(|k_ele| k_ele.kanji.as_str())
+        .collect();
+    let readings: HashSet<&str> = entry
+        .reading_entries
+        .iter()
+        .map(|r_ele| r_ele.kana.as_str())
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    let mut report = |kind: DiagnosticKind, message: String| {
+        diagnostics.push(Diagnostic::new(kind, message, Some(entry.id), position))
+    };
+
+    for r_ele in &entry.reading_entries {
+        for restr in r_ele.related_kanji.iter().filter(|restr| !kanji.contains(restr.as_str())) {
+            report(
+                DiagnosticKind::MissingRestrictedKanji,
+                format!(
+                    "re_restr \"{}\" of reading \"{}\" does not match any kanji",
+                    restr, r_ele.kana
+                ),
+            );
+        }
+    }
+
+    for (i, sense) in entry.senses.iter().enumerate() {
+        for stagk in sense.only_kanji.iter().filter(|stagk| !kanji.contains(stagk.as_str())) {
+            report(
+                DiagnosticKind::MissingSenseKanji,
+                format!("stagk \"{}\" of sense {} does not match any kanji", stagk, i + 1),
+            );
+        }
+        for stagr in sense
+            .only_readings
+            .iter()
+            .filter(|stagr| !readings.contains(stagr.as_str()))
+        {
+            report(
+                DiagnosticKind::MissingSenseReading,
+                format!("stagr \"{}\" of sense {} does not match any reading", stagr, i + 1),
+            );
+        }
+    }
+
+    diagnostics
+}
+
+/// Receives the diagnostics reported while reading entries.
+///
+/// Returning an error from `report` stops reading.
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), Error>;
+}
+
+/// The default sink, which prints each diagnostic to stderr.
+pub struct StderrSink;
+
+impl DiagnosticSink for StderrSink {
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), Error> {
+        match diagnostic.severity {
+            Severity::Warning => eprintln!("WARNING: {}", diagnostic),
+            Severity::Error => eprintln!("ERROR: {}", diagnostic),
+        }
+        Ok(())
+    }
+}
+
+/// Collects the diagnostics.
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), Error> {
+        self.push(diagnostic);
+        Ok(())
+    }
+}
+
+/// Allows a sink to be inspected after it has been handed to an `EntryReader`.
+impl<S: DiagnosticSink> DiagnosticSink for Rc<RefCell<S>> {
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), Error> {
+        self.borrow_mut().report(diagnostic)
+    }
+}
+
+#[test]
+fn test_check_entry() {
+    use parser::EntryReader;
+    use serde_json;
+
+    let xml = r#"<JMdict><entry><ent_seq>1</ent_seq>
+<k_ele><keb>延べる</keb></k_ele>
+<r_ele><reb>のべる</reb><re_restr>伸べる</re_restr></r_ele>
+<sense><stagk>延べる</stagk><stagr>のべる</stagr><gloss>to postpone</gloss></sense>
+<sense><stagk>伸べる</stagk><stagr>のびる</stagr><gloss>to extend</gloss></sense>
+</entry></JMdict>"#;
+    let entry = EntryReader::new(xml.as_bytes()).next().unwrap().unwrap();
+    let position = Position { line: 1, column: 9 };
+    let diagnostics = check_entry(&entry, position);
+
+    let kinds: Vec<DiagnosticKind> = diagnostics.iter().map(|d| d.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            DiagnosticKind::MissingRestrictedKanji,
+            DiagnosticKind::MissingSenseKanji,
+            DiagnosticKind::MissingSenseReading,
+        ]
+    );
+    assert_eq!(
+        diagnostics[1].to_string(),
+        "stagk \"伸べる\" of sense 2 does not match any kanji in entry 1 at line 1, column 9"
+    );
+    assert_eq!(
+        serde_json::to_value(&diagnostics[2]).unwrap(),
+        json!({
+            "severity": "error",
+            "kind": "missing_sense_reading",
+            "message": "stagr \"のびる\" of sense 2 does not match any reading",
+            "entry": 1,
+            "line": 1,
+            "column": 9,
+        })
+    );
+}
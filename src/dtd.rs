@@ -0,0 +1,94 @@
+use failure::Error;
+use std::collections::{BTreeMap, HashMap};
+
+/// Maps entity codes (e.g. "adj-i") to their expansion (e.g. "adjective (keiyoushi)").
+pub type EntityTable = BTreeMap<String, String>;
+
+/// Maps entity expansions back to the codes that have them.
+///
+/// Nothing stops two entities from sharing an expansion, e.g. a ke_inf and re_inf entity with the
+/// same description, so each expansion maps to all of its codes.
+pub type ExpansionTable = HashMap<String, Vec<String>>;
+
+/// Extract the general entity declarations from the internal subset of a DOCTYPE declaration.
+///
+/// This is not a general-purpose DTD parser. It simply skips over comments and any declarations
+/// other than `<!ENTITY name "expansion">`, which is all JMDict uses entities for.
+pub fn parse_entity_declarations(doctype: &str) -> Result<EntityTable, Error> {
+    let mut entities = EntityTable::new();
+    let mut rest = doctype;
+
+    while let Some(start) = rest.find("<!") {
+        rest = &rest[start..];
+
+        if rest.starts_with("<!--") {
+            match rest.find("-->") {
+                Some(end) => rest = &rest[end + 3..],
+                None => bail!("Unterminated comment in DOCTYPE"),
+            }
+            continue;
+        }
+
+        if !rest.starts_with("<!ENTITY") {
+            rest = &rest[2..];
+            continue;
+        }
+        rest = rest["<!ENTITY".len()..].trim_start();
+
+        // Skip parameter entities
+        if rest.starts_with('%') {
+            continue;
+        }
+
+        let name_end = match rest.find(char::is_whitespace) {
+            Some(name_end) => name_end,
+            None => bail!("Unterminated entity declaration in DOCTYPE"),
+        };
+        let name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+
+        let quote = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => bail!("Expected quoted value for entity {} in DOCTYPE", name),
+        };
+        let value_end = match rest[1..].find(quote) {
+            Some(value_end) => value_end + 1,
+            None => bail!("Unterminated value for entity {} in DOCTYPE", name),
+        };
+        entities.insert(name.to_owned(), rest[1..value_end].to_owned());
+        rest = &rest[value_end + 1..];
+    }
+
+    Ok(entities)
+}
+
+/// Invert an `EntityTable` so that we can read documents whose entities have been expanded.
+pub fn expansion_table(entities: &EntityTable) -> ExpansionTable {
+    let mut expansions = ExpansionTable::new();
+    for (code, expansion) in entities {
+        expansions
+            .entry(expansion.clone())
+            .or_default()
+            .push(code.clone());
+    }
+    expansions
+}
+
+#[test]
+fn test_parse_entity_declarations() {
+    let doctype = r#"JMdict [
+<!ELEMENT JMdict (entry*)>
+<!-- <!ENTITY commented "out"> -->
+<!ENTITY adj-i "adjective (keiyoushi)">
+<!ENTITY v2a-s "Nidan verb with 'u' ending (archaic)">
+<!ENTITY  quoted   'single `quoted'>
+<!ENTITY % parameter "ignored">
+]"#;
+    let entities = parse_entity_declarations(doctype).unwrap();
+    assert_eq!(entities.len(), 3);
+    assert_eq!(entities["adj-i"], "adjective (keiyoushi)");
+    assert_eq!(entities["v2a-s"], "Nidan verb with 'u' ending (archaic)");
+    assert_eq!(entities["quoted"], "single `quoted");
+
+    assert!(parse_entity_declarations("JMdict [ <!ENTITY unterminated \"value>").is_err());
+}
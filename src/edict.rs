@@ -0,0 +1,206 @@
+//! Export of entries in the EDICT2 text format used by the original EDICT dictionary files, e.g.
+//!
+//! ```text
+//! 延べる;伸べる;展べる [のべる] /(v1,vt) (1) to lay out (a futon)/to make (bed)/.../EntL1176390/
+//! ```
+//!
+//! Each entry is written on a single line with its kanji, readings and the senses in one language.
+//! Kanji and readings are followed by their info codes and (P) if they have a common priority, and
+//! readings restricted by re_restr are followed by the kanji they apply to. Senses are numbered
+//! when there is more than one and are prefixed by their part of speech (when given), misc codes,
+//! fields and other annotations. Entries with no senses in the language are omitted.
+//!
+//! Unlike the original files, which used EUC-JP, the output is UTF-8.
+
+use failure::{Error, ResultExt};
+use jmdict::entities::Entity;
+use jmdict::{CrossReference, Entry, PriorityVec, ReleaseInfo, Sense};
+use std::io::Write;
+
+/// Write the entries to `out` in the EDICT2 format using the senses in `lang` (e.g. "eng"),
+/// returning the number of entries written.
+///
+/// The first line is a header describing the file since tools that read EDICT files skip it.
+pub fn export_entries<W: Write>(
+    entries: &[Entry],
+    lang: &str,
+    release: &ReleaseInfo,
+    mut out: W,
+) -> Result<usize, Error> {
+    let created = release.created.as_ref().map_or("unknown", String::as_str);
+    writeln!(
+        out,
+        "　？？？ /EDICT2 Japanese-Multilingual Electronic Dictionary Files/Copyright Electronic \
+         Dictionary Research & Development Group/Language: {}/Created: {}/",
+        lang, created
+    ).context("Failed to write header")?;
+
+    let mut count = 0;
+    for entry in entries {
+        if let Some(line) = entry_line(entry, lang) {
+            writeln!(out, "{}", line).context("Failed to write entry")?;
+            count += 1;
+        }
+    }
+    out.flush()?;
+
+    Ok(count)
+}
+
+/// The EDICT2 line for the entry, if it has any senses in `lang`.
+fn entry_line(entry: &Entry, lang: &str) -> Option<String> {
+    let senses: Vec<&Sense> = entry
+        .senses
+        .iter()
+        .filter(|sense| sense.language() == lang && !sense.glosses.is_empty())
+        .collect();
+    if senses.is_empty() {
+        return None;
+    }
+
+    let kanji: Vec<String> = entry
+        .kanji_entries
+        .iter()
+        .map(|k_ele| k_ele.kanji.clone() + &codes(&k_ele.info) + common(&k_ele.priority))
+        .collect();
+    let readings: Vec<String> = entry
+        .reading_entries
+        .iter()
+        .map(|r_ele| {
+            let mut reading = r_ele.kana.clone();
+            if !r_ele.related_kanji.is_empty() {
+                reading += &format!("({})", r_ele.related_kanji.join(","));
+            }
+            reading + &codes(&r_ele.info) + common(&r_ele.priority)
+        })
+        .collect();
+
+    let mut line = if kanji.is_empty() {
+        readings.join(";")
+    } else {
+        format!("{} [{}]", kanji.join(";"), readings.join(";"))
+    };
+    line.push_str(" /");
+    for (i, sense) in senses.iter().enumerate() {
+        let number = if senses.len() > 1 { Some(i + 1) } else { None };
+        line.push_str(&sense_fields(sense, number));
+        line.push('/');
+    }
+
+    let is_common = |priority: &PriorityVec| priority.iter().any(|priority| priority.is_common());
+    if entry.kanji_entries.iter().any(|k_ele| is_common(&k_ele.priority))
+        || entry.reading_entries.iter().any(|r_ele| is_common(&r_ele.priority))
+    {
+        line.push_str("(P)/");
+    }
+    line.push_str(&format!("EntL{}/", entry.id));
+
+    Some(line)
+}
+
+/// The glosses of the sense separated by slashes, with the first one prefixed by the sense
+/// number and annotations, e.g. "(v1,vt) (1) to lay out (a futon)/to make (bed)".
+fn sense_fields(sense: &Sense, number: Option<usize>) -> String {
+    let mut prefix: Vec<String> = Vec::new();
+    if !sense.part_of_speech.is_empty() {
+        prefix.push(codes(&sense.part_of_speech));
+    }
+    if let Some(number) = number {
+        prefix.push(format!("({})", number));
+    }
+    if !sense.misc.is_empty() {
+        prefix.push(codes(&sense.misc));
+    }
+    prefix.extend(sense.field.iter().map(|field| format!("{{{}}}", field.code())));
+    prefix.extend(sense.dialect.iter().map(|dialect| format!("({}:)", dialect.code())));
+    let only: Vec<&str> = sense
+        .only_kanji
+        .iter()
+        .chain(&sense.only_readings)
+        .map(String::as_str)
+        .collect();
+    if !only.is_empty() {
+        prefix.push(format!("({} only)", only.join(",")));
+    }
+    prefix.extend(sense.sense_info.iter().map(|info| format!("({})", info)));
+    for source in &sense.lang_sources {
+        let label = if source.wasei { "wasei" } else { source.lang.as_str() };
+        prefix.push(match source.original {
+            Some(ref original) => format!("({}: {})", label, original),
+            None => format!("({}:)", label),
+        });
+    }
+    if !sense.cross_refs.is_empty() {
+        prefix.push(format!("(See {})", refs(&sense.cross_refs)));
+    }
+    if !sense.antonyms.is_empty() {
+        prefix.push(format!("(ant: {})", refs(&sense.antonyms)));
+    }
+
+    let glosses: Vec<&str> = sense.glosses.iter().map(|g| g.text.as_str()).collect();
+    prefix.push(glosses.join("/"));
+    prefix.join(" ")
+}
+
+/// The entity codes in parentheses, e.g. "(v1,vt)", or an empty string if there are none.
+fn codes<T: Entity>(values: &[T]) -> String {
+    if values.is_empty() {
+        return String::new();
+    }
+    let codes: Vec<&str> = values.iter().map(Entity::code).collect();
+    format!("({})", codes.join(","))
+}
+
+/// "(P)" if any of the priorities mark the word as common.
+fn common(priority: &PriorityVec) -> &'static str {
+    if priority.iter().any(|priority| priority.is_common()) {
+        "(P)"
+    } else {
+        ""
+    }
+}
+
+fn refs(refs: &[CrossReference]) -> String {
+    let refs: Vec<String> = refs.iter().map(ToString::to_string).collect();
+    refs.join(",")
+}
+
+#[test]
+fn test_export_entries() {
+    use jmdict::EntryReader;
+
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    let entries = reader
+        .by_ref()
+        .collect::<Result<Vec<Entry>, Error>>()
+        .unwrap();
+    let mut out: Vec<u8> = Vec::new();
+    let count = export_entries(&entries, "eng", &reader.release_info(), &mut out).unwrap();
+    assert_eq!(count, 13);
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 14);
+    assert!(lines[0].ends_with("/Language: eng/Created: 2018-02-14/"));
+    assert_eq!(
+        lines[1],
+        "ヽ /(unc) repetition mark in katakana/EntL1000000/"
+    );
+    assert_eq!(
+        lines[3],
+        "ＣＤプレーヤー(P);ＣＤプレイヤー \
+         [シーディープレーヤー(ＣＤプレーヤー)(P);シーディープレイヤー(ＣＤプレイヤー)] \
+         /(n) CD player/(P)/EntL1000110/"
+    );
+
+    let nobe = lines.iter().find(|line| line.ends_with("/EntL1176390/")).unwrap();
+    assert!(nobe.starts_with("延べる;伸べる;展べる [のべる] /(v1,vt) (1) to lay out (a futon)/"));
+    assert!(nobe.contains("/(2) (延べる,伸べる only) to postpone/to extend/"));
+
+    let mut out: Vec<u8> = Vec::new();
+    assert_eq!(export_entries(&entries, "ger", &ReleaseInfo::default(), &mut out).unwrap(), 11);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("　？？？ /EDICT2"));
+    assert!(out.contains("/Created: unknown/"));
+    assert!(out.contains(" /(m) CD-Spieler/(m) CD-Player/(P)/EntL1000110/"));
+}
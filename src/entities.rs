@@ -0,0 +1,27 @@
+//! Enums for the entity codes used in JMDict, e.g. &adj-i; or &uk;.
+//!
+//! The enums are generated by build.rs from the lists in the entities directory so that adding a
+//! new entity code is simply a matter of adding a line to the appropriate list.
+
+/// A value that is represented in JMDict as an entity reference.
+pub trait Entity: Sized {
+    /// Look up the value for an entity code, e.g. "adj-i" (without the & and ;).
+    fn from_code(code: &str) -> Option<Self>;
+
+    /// The entity code for this value, e.g. "adj-i".
+    fn code(&self) -> &'static str;
+}
+
+include!(concat!(env!("OUT_DIR"), "/entities.rs"));
+
+#[test]
+fn test_entity_codes() {
+    assert_eq!(PartOfSpeech::from_code("adj-i"), Some(PartOfSpeech::AdjI));
+    assert_eq!(PartOfSpeech::AdjI.code(), "adj-i");
+    assert_eq!(PartOfSpeech::from_code("v5k-s"), Some(PartOfSpeech::V5kS));
+    assert_eq!(KanjiInfo::from_code("iK"), Some(KanjiInfo::IK));
+    assert_eq!(KanjiInfo::from_code("ik"), Some(KanjiInfo::Ik));
+    assert_eq!(Misc::from_code("uk"), Some(Misc::Uk));
+    assert_eq!(Misc::from_code("adj-i"), None);
+    assert_eq!(Dialect::Ksb.code(), "ksb");
+}
@@ -0,0 +1,305 @@
+//! The types representing a JMDict entry.
+//!
+//! These types serialize (e.g. to JSON) using their field names which are shared by the CouchDB
+//! documents and the JSON export and should therefore be treated as a stable format. To keep the
+//! output compact, empty lists, absent values and false flags are omitted. Entity values such as
+//! parts of speech serialize as their entity code (e.g. "adj-i") and priorities as their original
+//! code (e.g. "nf17"). The CouchDB documents and JSON export additionally include `_id` (the
+//! ent_seq as a string), `priority_score` (see `Entry::priority_score`) and `search_keys` (see
+//! `Entry::search_keys`). Cross-references only have a `target` once `resolve_cross_refs` has
+//! resolved them.
+//!
+//! For example, a minimal entry serializes as:
+//!
+//! ```json
+//! {
+//!   "id": 1000000,
+//!   "reading_entries": [{ "kana": "ヽ" }],
+//!   "senses": [{
+//!     "part_of_speech": ["unc"],
+//!     "glosses": [{ "text": "repetition mark in katakana" }]
+//!   }]
+//! }
+//! ```
+
+use entities::{Dialect, Entity, Field, KanjiInfo, Misc, NameType, PartOfSpeech, ReadingInfo};
+use failure::Error;
+use kana;
+use priority::{priority_score, Priority};
+use smallvec::SmallVec;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+pub type InfoVec<T> = SmallVec<[T; 4]>;
+pub type PriorityVec = SmallVec<[Priority; 4]>;
+
+/// entry from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Entry {
+    /// ent_seq
+    pub id: u32,
+    /// k_ele children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kanji_entries: Vec<KanjiEntry>,
+    /// r_ele children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reading_entries: Vec<ReadingEntry>,
+    /// sense children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub senses: Vec<Sense>,
+}
+
+impl Entry {
+    /// The priority score of the most common kanji or reading element of this entry.
+    pub fn priority_score(&self) -> u32 {
+        let kanji_scores = self.kanji_entries.iter().map(KanjiEntry::priority_score);
+        let reading_scores = self.reading_entries.iter().map(ReadingEntry::priority_score);
+        kanji_scores.chain(reading_scores).max().unwrap_or(0)
+    }
+
+    /// The keys under which this entry can be found by typing hiragana or romaji, gathered from
+    /// all of its readings. See `kana::search_keys`.
+    pub fn search_keys(&self) -> Vec<String> {
+        let u_verb = self.senses
+            .iter()
+            .flat_map(|sense| &sense.part_of_speech)
+            .any(|pos| pos.code().starts_with("v5u"));
+        let keys: BTreeSet<String> = self.reading_entries
+            .iter()
+            .flat_map(|r_ele| kana::search_keys(&r_ele.kana, u_verb))
+            .collect();
+        keys.into_iter().collect()
+    }
+}
+
+/// k_ele from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KanjiEntry {
+    /// keb
+    pub kanji: String,
+    /// ke_inf
+    #[serde(skip_serializing_if = "SmallVec::is_empty")]
+    pub info: InfoVec<KanjiInfo>,
+    /// ke_pri
+    #[serde(skip_serializing_if = "SmallVec::is_empty")]
+    pub priority: PriorityVec,
+}
+
+impl KanjiEntry {
+    pub fn priority_score(&self) -> u32 {
+        priority_score(&self.priority)
+    }
+}
+
+/// r_ele from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReadingEntry {
+    /// reb
+    pub kana: String,
+    /// re_nokanji
+    #[serde(skip_serializing_if = "is_false")]
+    pub no_kanji: bool,
+    /// re_restr
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related_kanji: Vec<String>,
+    /// re_inf
+    #[serde(skip_serializing_if = "SmallVec::is_empty")]
+    pub info: InfoVec<ReadingInfo>,
+    /// re_pri
+    #[serde(skip_serializing_if = "SmallVec::is_empty")]
+    pub priority: PriorityVec,
+}
+
+impl ReadingEntry {
+    pub fn priority_score(&self) -> u32 {
+        priority_score(&self.priority)
+    }
+}
+
+/// sense from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sense {
+    /// stagk
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub only_kanji: Vec<String>,
+    /// stagr
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub only_readings: Vec<String>,
+    /// pos
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub part_of_speech: Vec<PartOfSpeech>,
+    /// xref
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cross_refs: Vec<CrossReference>,
+    /// ant
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub antonyms: Vec<CrossReference>,
+    /// field
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub field: Vec<Field>,
+    /// misc
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub misc: Vec<Misc>,
+    /// s_inf
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sense_info: Vec<String>,
+    /// lsource
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lang_sources: Vec<LangSource>,
+    /// dial
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dialect: Vec<Dialect>,
+    /// gloss
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub glosses: Vec<Gloss>,
+
+    /// The language of this sense.
+    /// In JMDict this is annotated onto each gloss, but all glosses for a given sense have the same
+    /// language so we move this to the sense because it's more compact and allows us to create
+    /// per-language views more easily.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+impl Sense {
+    /// The language of this sense, where senses without an explicit language are English.
+    pub fn language(&self) -> &str {
+        self.lang.as_ref().map_or("eng", String::as_str)
+    }
+}
+
+/// gloss from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Gloss {
+    pub text: String,
+    /// g_type
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub gloss_type: Option<GlossType>,
+    /// g_gend, e.g. "fem"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+}
+
+impl Gloss {
+    /// A gloss with no type or gender.
+    pub fn new<S: Into<String>>(text: S) -> Gloss {
+        Gloss {
+            text: text.into(),
+            gloss_type: None,
+            gender: None,
+        }
+    }
+}
+
+/// The values of the g_type attribute, which serialize as their attribute value (e.g. "lit").
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GlossType {
+    /// A literal translation
+    Lit,
+    /// A figurative translation
+    Fig,
+    /// An explanation rather than a translation
+    Expl,
+    /// A trademark
+    Tm,
+}
+
+impl FromStr for GlossType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<GlossType, Error> {
+        match value {
+            "lit" => Ok(GlossType::Lit),
+            "fig" => Ok(GlossType::Fig),
+            "expl" => Ok(GlossType::Expl),
+            "tm" => Ok(GlossType::Tm),
+            _ => bail!("Unexpected g_type value \"{}\"", value),
+        }
+    }
+}
+
+/// xref or ant from jmdict schema, e.g. "何方・どちら・1"
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CrossReference {
+    pub kanji_or_reading: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sense_index: Option<u8>,
+    /// The ent_seq of the entry this refers to, once resolved by `resolve_cross_refs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<u32>,
+}
+
+/// Formats the reference as it appears in JMDict, e.g. "何方・どちら・1".
+impl fmt::Display for CrossReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.kanji_or_reading)?;
+        if let Some(ref reading) = self.reading {
+            write!(f, "・{}", reading)?;
+        }
+        if let Some(sense_index) = self.sense_index {
+            write!(f, "・{}", sense_index)?;
+        }
+        Ok(())
+    }
+}
+
+/// entry from jmnedict schema
+///
+/// Names share the k_ele and r_ele elements of JMDict entries but have trans elements in place of
+/// senses.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NameEntry {
+    /// ent_seq
+    pub id: u32,
+    /// k_ele children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kanji_entries: Vec<KanjiEntry>,
+    /// r_ele children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reading_entries: Vec<ReadingEntry>,
+    /// trans children
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<Translation>,
+}
+
+/// trans from jmnedict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Translation {
+    /// name_type
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub name_type: Vec<NameType>,
+    /// xref
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cross_refs: Vec<CrossReference>,
+    /// trans_det
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+
+    /// The language of the trans_det elements, moved to the translation as with `Sense::lang`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+/// lsource from jmdict schema
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LangSource {
+    /// xml:lang
+    pub lang: String,
+    /// The source word or phrase, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    /// ls_type="part"
+    #[serde(skip_serializing_if = "is_false")]
+    pub partial: bool,
+    /// ls_wasei="y"
+    #[serde(skip_serializing_if = "is_false")]
+    pub wasei: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
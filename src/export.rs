@@ -0,0 +1,155 @@
+use failure::{Error, ResultExt};
+use jmdict::Entry;
+use serde_json;
+use serde_json::Value;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use couch::entry_doc;
+
+/// The formats we can write entries in instead of syncing them to CouchDB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// A single JSON array of entries
+    Json,
+    /// One JSON entry per line
+    Ndjson,
+    /// A SQLite database with a table for each part of an entry (see the `sqlite` module)
+    Sqlite,
+    /// A Yomitan dictionary archive (see the `yomitan` module)
+    Yomitan,
+    /// EDICT2 text with one line per entry (see the `edict` module)
+    Edict2,
+}
+
+impl OutputFormat {
+    /// The corresponding `JsonFormat` if this is one of the JSON formats.
+    pub fn json_format(self) -> Option<JsonFormat> {
+        match self {
+            OutputFormat::Json => Some(JsonFormat::Json),
+            OutputFormat::Ndjson => Some(JsonFormat::Ndjson),
+            OutputFormat::Sqlite | OutputFormat::Yomitan | OutputFormat::Edict2 => None,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<OutputFormat, Error> {
+        match format {
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            "yomitan" => Ok(OutputFormat::Yomitan),
+            "edict2" => Ok(OutputFormat::Edict2),
+            _ => bail!(
+                "Unrecognized output format \"{}\" (expected json, ndjson, sqlite, yomitan or \
+                 edict2)",
+                format
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Yomitan => "yomitan",
+            OutputFormat::Edict2 => "edict2",
+        })
+    }
+}
+
+/// The output formats that write each entry or document as JSON, which are the only ones
+/// supported for every input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonFormat {
+    /// A single JSON array
+    Json,
+    /// One JSON value per line
+    Ndjson,
+}
+
+/// Write each of the supplied entries as it is parsed, returning the number of entries written.
+///
+/// Entries are written using the same representation as the (unsplit) CouchDB documents. As with
+/// those, the `target` of each cross-reference is only included if the entries have been passed
+/// through `jmdict::resolve_cross_refs` first, as the command line tool does.
+pub fn export_entries<I, W>(entries: I, format: JsonFormat, out: W) -> Result<usize, Error>
+where
+    I: Iterator<Item = Result<Entry, Error>>,
+    W: Write,
+{
+    export_docs(entries.map(|entry| entry_doc(&entry?)), format, out)
+}
+
+/// Write each of the supplied documents, returning the number of documents written.
+pub fn export_docs<I, W>(docs: I, format: JsonFormat, mut out: W) -> Result<usize, Error>
+where
+    I: Iterator<Item = Result<Value, Error>>,
+    W: Write,
+{
+    let mut count = 0;
+
+    if format == JsonFormat::Json {
+        out.write_all(b"[")?;
+    }
+
+    for doc in docs {
+        let doc = doc?;
+        match format {
+            JsonFormat::Json => {
+                out.write_all(if count == 0 { b"\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut out, &doc).context("Failed to write document")?;
+            }
+            JsonFormat::Ndjson => {
+                serde_json::to_writer(&mut out, &doc).context("Failed to write document")?;
+                out.write_all(b"\n")?;
+            }
+        }
+        count += 1;
+    }
+
+    if format == JsonFormat::Json {
+        out.write_all(b"\n]\n")?;
+    }
+    out.flush()?;
+
+    Ok(count)
+}
+
+#[test]
+fn test_export_entries() {
+    use jmdict::EntryReader;
+    use serde_json::Value;
+
+    let mut out: Vec<u8> = Vec::new();
+    let reader = EntryReader::from_file("data/sample.xml").unwrap();
+    assert_eq!(export_entries(reader, JsonFormat::Ndjson, &mut out).unwrap(), 13);
+    let lines: Vec<Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 13);
+    assert_eq!(lines[0]["_id"], "1000000");
+    assert_eq!(lines[0]["reading_entries"][0]["kana"], "ヽ");
+    assert_eq!(lines[0]["senses"][0]["part_of_speech"][0], "unc");
+    assert!(lines[0].get("kanji_entries").is_none());
+
+    let mut out: Vec<u8> = Vec::new();
+    let reader = EntryReader::from_file("data/sample.xml").unwrap();
+    export_entries(reader, JsonFormat::Json, &mut out).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 13);
+    assert_eq!(json[12], lines[12]);
+
+    let mut out: Vec<u8> = Vec::new();
+    export_entries(Vec::new().into_iter(), JsonFormat::Json, &mut out).unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&out).unwrap(), json!([]));
+}
@@ -0,0 +1,284 @@
+use failure::{Error, ResultExt};
+use flate2::bufread::MultiGzDecoder;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Open a JMDict file for reading, where a path of "-" means stdin.
+///
+/// If the input is gzip-compressed (as with the JMdict.gz files distributed by the EDRDG) it is
+/// decompressed on the fly.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn BufRead>, Error> {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        return decompress(BufReader::new(io::stdin()));
+    }
+
+    let file = File::open(path).context("Could not read from file")?;
+    decompress(BufReader::new(file))
+}
+
+/// Wrap the supplied input so that it is decompressed if it starts with the gzip magic bytes.
+pub fn decompress<R: BufRead + 'static>(mut input: R) -> Result<Box<dyn BufRead>, Error> {
+    let is_gzip = input
+        .fill_buf()
+        .context("Could not read input")?
+        .starts_with(&GZIP_MAGIC);
+
+    if is_gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(input))))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+/// A location in the input. Lines and columns are counted from 1 and columns count characters
+/// rather than bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn advance(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if byte & 0xc0 != 0x80 {
+                // Only count the first byte of each UTF-8 sequence
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Wraps an input so that byte offsets into it (e.g. from `Reader::buffer_position`) can be
+/// converted to line and column positions using the accompanying `Positions`.
+pub struct PositionTracker<R> {
+    inner: R,
+    positions: Positions,
+}
+
+impl<R: BufRead> PositionTracker<R> {
+    pub fn new(inner: R) -> (PositionTracker<R>, Positions) {
+        let positions = Positions::default();
+        let tracker = PositionTracker {
+            inner,
+            positions: positions.clone(),
+        };
+        (tracker, positions)
+    }
+}
+
+impl<R: BufRead> Read for PositionTracker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.positions.record(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for PositionTracker<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Nothing has been consumed since the last fill_buf so this simply returns the same buffer
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.positions.record(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Wraps an input so that the bytes read from it are hashed, making the hash available through
+/// the accompanying `InputHash`.
+pub struct HashingReader<R> {
+    inner: R,
+    hash: InputHash,
+}
+
+impl<R: BufRead> HashingReader<R> {
+    pub fn new(inner: R) -> (HashingReader<R>, InputHash) {
+        let hash = InputHash::default();
+        let reader = HashingReader {
+            inner,
+            hash: hash.clone(),
+        };
+        (reader, hash)
+    }
+}
+
+impl<R: BufRead> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hash.hasher.borrow_mut().update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for HashingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // As with PositionTracker, this returns the buffer from the last fill_buf
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hash.hasher.borrow_mut().update(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// The SHA-256 hash of the input read so far by a `HashingReader`.
+#[derive(Clone, Default)]
+pub struct InputHash {
+    hasher: Rc<RefCell<Sha256>>,
+}
+
+impl InputHash {
+    /// The hash as lowercase hex.
+    pub fn hex(&self) -> String {
+        let digest = self.hasher.borrow().clone().finalize();
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Converts byte offsets in the input read by a `PositionTracker` to line and column positions.
+///
+/// Only the input read since the last call to `mark` is kept so offsets before that resolve to
+/// the marked position.
+#[derive(Clone, Default)]
+pub struct Positions {
+    state: Rc<RefCell<PositionState>>,
+}
+
+struct PositionState {
+    /// The offset and position of the start of `recent`
+    mark_offset: usize,
+    mark: Position,
+    /// The bytes read since the mark
+    recent: Vec<u8>,
+}
+
+impl Default for PositionState {
+    fn default() -> PositionState {
+        PositionState {
+            mark_offset: 0,
+            mark: Position { line: 1, column: 1 },
+            recent: Vec::new(),
+        }
+    }
+}
+
+impl Positions {
+    fn record(&self, bytes: &[u8]) {
+        self.state.borrow_mut().recent.extend_from_slice(bytes);
+    }
+
+    /// Discard the input before `offset` since we will no longer need to resolve positions in it.
+    pub fn mark(&self, offset: usize) {
+        let mut state = self.state.borrow_mut();
+        let len = offset
+            .saturating_sub(state.mark_offset)
+            .min(state.recent.len());
+        let mut mark = state.mark;
+        mark.advance(&state.recent[..len]);
+        state.mark = mark;
+        state.mark_offset += len;
+        state.recent.drain(..len);
+    }
+
+    /// The line and column of the byte at `offset`.
+    pub fn position(&self, offset: usize) -> Position {
+        let state = self.state.borrow();
+        let len = offset
+            .saturating_sub(state.mark_offset)
+            .min(state.recent.len());
+        let mut position = state.mark;
+        position.advance(&state.recent[..len]);
+        position
+    }
+}
+
+#[test]
+fn test_position_tracker() {
+    let (mut tracker, positions) = PositionTracker::new("<a>\n  <b>読み</b>\n</a>".as_bytes());
+    let mut line = String::new();
+    tracker.read_line(&mut line).unwrap();
+    line.clear();
+    tracker.read_line(&mut line).unwrap();
+
+    assert_eq!(positions.position(0), Position { line: 1, column: 1 });
+    assert_eq!(positions.position(6), Position { line: 2, column: 3 });
+    // The column counts the kana as one character each
+    assert_eq!(positions.position(15), Position { line: 2, column: 8 });
+
+    positions.mark(6);
+    assert_eq!(positions.position(0), Position { line: 2, column: 3 });
+    assert_eq!(positions.position(15), Position { line: 2, column: 8 });
+    assert_eq!(positions.position(1000), Position { line: 3, column: 1 });
+    assert_eq!(positions.position(6).to_string(), "line 2, column 3");
+}
+
+#[test]
+fn test_hashing_reader() {
+    let (mut reader, hash) = HashingReader::new("abc\ndef".as_bytes());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(
+        hash.hex(),
+        "edeaaff3f1774ad2888673770c6d64097e391bc362d7d6fb34982ddf0efd18cb"
+    );
+    reader.read_to_string(&mut line).unwrap();
+    assert_eq!(
+        hash.hex(),
+        "d53d6b91af7caf8fe3d8021f116270137c0079d579a1e16965da80c2ed138ffb"
+    );
+}
+
+#[test]
+fn test_decompress() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Read, Write};
+
+    let xml = "<JMdict></JMdict>";
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(xml.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert!(compressed.starts_with(&GZIP_MAGIC));
+
+    let mut output = String::new();
+    decompress(io::Cursor::new(compressed))
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert_eq!(output, xml);
+
+    let mut output = String::new();
+    decompress(io::Cursor::new(xml.as_bytes().to_vec()))
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert_eq!(output, xml);
+}
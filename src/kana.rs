@@ -0,0 +1,434 @@
+//! Conversion of kana readings into the keys used to search for them.
+//!
+//! Users look words up by typing hiragana, even for words written in katakana, or by typing
+//! romaji using whichever romanization they learned. `search_keys` produces a key for each of
+//! these so that a single view can serve all of them.
+
+use std::collections::BTreeSet;
+
+/// The systems we use to romanize readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Romanization {
+    /// Modified Hepburn, e.g. "shinbun", "chizu"
+    Hepburn,
+    /// Kunrei-shiki, e.g. "sinbun", "tizu"
+    Kunrei,
+}
+
+/// The search keys for a reading, sorted and without duplicates.
+///
+/// These are the normalized hiragana (see `normalize`), its Hepburn and Kunrei romanizations, and
+/// the Hepburn romanization with long vowels shortened (e.g. "tokyo" for "toukyou"). `u_verb`
+/// indicates that the reading is a godan verb ending in "う" (e.g. "おもう"), whose final "う" is
+/// not part of a long vowel.
+pub fn search_keys(kana: &str, u_verb: bool) -> Vec<String> {
+    let full_width = to_full_width(kana);
+    let hiragana = to_hiragana(&full_width);
+    let short = shorten_long_vowels(&hiragana, is_katakana(&full_width), u_verb);
+
+    let mut keys = BTreeSet::new();
+    keys.insert(normalize(kana));
+    keys.insert(to_romaji(&short, Romanization::Hepburn));
+    keys.insert(to_romaji(&hiragana, Romanization::Hepburn));
+    keys.insert(to_romaji(&hiragana, Romanization::Kunrei));
+    keys.into_iter().collect()
+}
+
+#[allow(clippy::manual_range_contains)]
+pub fn is_katakana(word: &str) -> bool {
+    word.chars().all(|c| c >= '\u{30a0}' && c <= '\u{30ff}')
+}
+
+/// Fold a reading into the form used for its hiragana search key.
+///
+/// Half-width katakana are converted to full-width, katakana to hiragana, long vowel marks to the
+/// vowel they extend, and small vowels (e.g. the "ぁ" of "ふぁ") to their full-size forms. The
+/// small "っ", "ゃ", "ゅ" and "ょ" are kept since they distinguish words.
+pub fn normalize(kana: &str) -> String {
+    let hiragana = to_hiragana(&to_full_width(kana));
+    let mut result = String::with_capacity(hiragana.len());
+    let mut previous: Option<char> = None;
+
+    for c in hiragana.chars() {
+        let c = match c {
+            'ー' => previous.and_then(vowel).unwrap_or(c),
+            'ぁ' => 'あ',
+            'ぃ' => 'い',
+            'ぅ' => 'う',
+            'ぇ' => 'え',
+            'ぉ' => 'お',
+            'ゎ' => 'わ',
+            'ゕ' => 'か',
+            'ゖ' => 'け',
+            _ => c,
+        };
+        result.push(c);
+        previous = Some(c);
+    }
+
+    result
+}
+
+/// The full-width equivalents of the half-width characters from U+FF61 to U+FF9F.
+const FULL_WIDTH: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノ\
+                          ハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+/// Convert half-width katakana (e.g. "ｶﾞ") to full-width (e.g. "ガ"), combining voiced sound
+/// marks with the kana they follow.
+pub fn to_full_width(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        let full = match c {
+            '\u{ff61}'..='\u{ff9f}' => FULL_WIDTH
+                .chars()
+                .nth(c as usize - 0xff61)
+                .unwrap(),
+            _ => {
+                result.push(c);
+                continue;
+            }
+        };
+
+        let combined = match (result.chars().last(), full) {
+            (Some('ウ'), '゛') => Some('ヴ'),
+            (Some('ワ'), '゛') => Some('ヷ'),
+            (Some('ヲ'), '゛') => Some('ヺ'),
+            // The voiced and semi-voiced forms of these follow them in the Unicode block
+            (Some(base), '゛') if "カキクケコサシスセソタチツテトハヒフヘホ".contains(base) => {
+                ::std::char::from_u32(base as u32 + 1)
+            }
+            (Some(base), '゜') if "ハヒフヘホ".contains(base) => {
+                ::std::char::from_u32(base as u32 + 2)
+            }
+            _ => None,
+        };
+        match combined {
+            Some(combined) => {
+                result.pop();
+                result.push(combined);
+            }
+            None => result.push(full),
+        }
+    }
+
+    result
+}
+
+/// Convert katakana to hiragana, leaving any other characters (including "ー") as they are.
+pub fn to_hiragana(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| match c {
+            'ァ'..='ヶ' | 'ヽ' | 'ヾ' => ::std::char::from_u32(c as u32 - 0x60).unwrap(),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Romanize a hiragana reading (e.g. as produced by `to_hiragana`).
+///
+/// Characters we don't know how to romanize are passed through unchanged.
+pub fn to_romaji(hiragana: &str, romanization: Romanization) -> String {
+    let chars: Vec<char> = hiragana.chars().collect();
+    let mut result = String::with_capacity(hiragana.len());
+    let mut geminate = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == 'っ' {
+            geminate = true;
+            i += 1;
+            continue;
+        }
+        if c == 'ー' {
+            if let Some(last) = result.chars().last().filter(|last| "aeiou".contains(*last)) {
+                result.push(last);
+            }
+            i += 1;
+            continue;
+        }
+
+        let (syllable, len) = match chars
+            .get(i + 1)
+            .and_then(|&next| digraph(c, next, romanization))
+        {
+            Some(syllable) => (syllable, 2),
+            None => match syllable(c, romanization) {
+                Some(syllable) => (syllable.to_owned(), 1),
+                None => (c.to_string(), 1),
+            },
+        };
+
+        // A small tsu doubles the consonant that follows it, except that Hepburn writes "tch"
+        // rather than "cch".
+        if geminate {
+            match syllable.chars().next() {
+                Some('c') => result.push('t'),
+                Some(first) if first.is_ascii_alphabetic() && !"aeioun".contains(first) => {
+                    result.push(first)
+                }
+                _ => (),
+            }
+            geminate = false;
+        }
+
+        result.push_str(&syllable);
+        i += len;
+    }
+
+    result
+}
+
+/// Drop the kana that lengthen the vowel of the kana before them from a hiragana reading, e.g.
+/// "とうきょう" to "ときょ".
+///
+/// These are "ー", a vowel that repeats the one before it (e.g. the "い" of "ちい") and an "う"
+/// following an "o" (e.g. the "う" of "とう"). Since katakana words write their long vowels with
+/// "ー", an "う" in them (e.g. "ソウル") is pronounced and is kept, as is the final "う" of a
+/// godan verb (see `search_keys`).
+fn shorten_long_vowels(hiragana: &str, katakana: bool, u_verb: bool) -> String {
+    let chars: Vec<char> = hiragana.chars().collect();
+    let mut result = String::with_capacity(hiragana.len());
+    let mut previous: Option<char> = None;
+
+    for (i, &c) in chars.iter().enumerate() {
+        let verb_ending = u_verb && i == chars.len() - 1;
+        let long = match (previous.and_then(vowel), c) {
+            (Some(_), 'ー') => true,
+            (Some('お'), 'う') => !(katakana || verb_ending),
+            (Some(vowel), _) => vowel == c,
+            _ => false,
+        };
+        if !long {
+            result.push(c);
+            previous = Some(c);
+        }
+    }
+
+    result
+}
+
+/// The vowel (as hiragana) that a long vowel mark following `c` extends.
+fn vowel(c: char) -> Option<char> {
+    let vowels = [
+        ('あ', "あぁかがさざただなはばぱまやゃらわゎゕ"),
+        ('い', "いぃきぎしじちぢにひびぴみりゐ"),
+        ('う', "うぅくぐすずつづぬふぶぷむゆゅるゔ"),
+        ('え', "えぇけげせぜてでねへべぺめれゑゖ"),
+        ('お', "おぉこごそぞとどのほぼぽもよょろを"),
+    ];
+    vowels
+        .iter()
+        .find(|&&(_, kana)| kana.contains(c))
+        .map(|&(vowel, _)| vowel)
+}
+
+/// Romanize a kana followed by a small kana, e.g. "しゃ" or "ふぁ".
+fn digraph(c: char, next: char, romanization: Romanization) -> Option<String> {
+    let hepburn = romanization == Romanization::Hepburn;
+
+    // Yōon, e.g. "きゃ"
+    let vowel = match next {
+        'ゃ' => Some("a"),
+        'ゅ' => Some("u"),
+        'ょ' => Some("o"),
+        _ => None,
+    };
+    if let Some(vowel) = vowel {
+        let consonant = match c {
+            'き' => "ky",
+            'ぎ' => "gy",
+            'し' if hepburn => "sh",
+            'し' => "sy",
+            'じ' | 'ぢ' if hepburn => "j",
+            'じ' | 'ぢ' => "zy",
+            'ち' if hepburn => "ch",
+            'ち' => "ty",
+            'に' => "ny",
+            'ひ' => "hy",
+            'び' => "by",
+            'ぴ' => "py",
+            'み' => "my",
+            'り' => "ry",
+            'ふ' => "fy",
+            _ => return None,
+        };
+        return Some(format!("{}{}", consonant, vowel));
+    }
+
+    // Sounds used in loanwords, e.g. "ふぁ" or "てぃ"
+    let syllable = match (c, next) {
+        ('し', 'ぇ') if hepburn => "she",
+        ('し', 'ぇ') => "sye",
+        ('じ', 'ぇ') if hepburn => "je",
+        ('じ', 'ぇ') => "zye",
+        ('ち', 'ぇ') if hepburn => "che",
+        ('ち', 'ぇ') => "tye",
+        ('つ', 'ぁ') => "tsa",
+        ('つ', 'ぃ') => "tsi",
+        ('つ', 'ぇ') => "tse",
+        ('つ', 'ぉ') => "tso",
+        ('て', 'ぃ') => "ti",
+        ('で', 'ぃ') => "di",
+        ('と', 'ぅ') => "tu",
+        ('ど', 'ぅ') => "du",
+        ('ふ', 'ぁ') => "fa",
+        ('ふ', 'ぃ') => "fi",
+        ('ふ', 'ぇ') => "fe",
+        ('ふ', 'ぉ') => "fo",
+        ('う', 'ぃ') => "wi",
+        ('う', 'ぇ') => "we",
+        ('う', 'ぉ') => "wo",
+        ('ゔ', 'ぁ') => "va",
+        ('ゔ', 'ぃ') => "vi",
+        ('ゔ', 'ぇ') => "ve",
+        ('ゔ', 'ぉ') => "vo",
+        ('い', 'ぇ') => "ye",
+        ('く', 'ぁ') => "kwa",
+        ('ぐ', 'ぁ') => "gwa",
+        _ => return None,
+    };
+    Some(syllable.to_owned())
+}
+
+/// Romanize a single kana.
+fn syllable(c: char, romanization: Romanization) -> Option<&'static str> {
+    if romanization == Romanization::Kunrei {
+        match c {
+            'し' => return Some("si"),
+            'じ' | 'ぢ' => return Some("zi"),
+            'ち' => return Some("ti"),
+            'つ' => return Some("tu"),
+            'づ' => return Some("zu"),
+            'ふ' => return Some("hu"),
+            _ => (),
+        }
+    }
+
+    Some(match c {
+        'あ' | 'ぁ' => "a",
+        'い' | 'ぃ' | 'ゐ' => "i",
+        'う' | 'ぅ' => "u",
+        'え' | 'ぇ' | 'ゑ' => "e",
+        'お' | 'ぉ' | 'を' => "o",
+        'か' | 'ゕ' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' | 'ゖ' => "ke",
+        'こ' => "ko",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'ざ' => "za",
+        'じ' | 'ぢ' => "ji",
+        'ず' | 'づ' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'だ' => "da",
+        'で' => "de",
+        'ど' => "do",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' | 'ゃ' => "ya",
+        'ゆ' | 'ゅ' => "yu",
+        'よ' | 'ょ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' | 'ゎ' => "wa",
+        'ん' => "n",
+        'ゔ' => "vu",
+        _ => return None,
+    })
+}
+
+#[test]
+#[allow(clippy::bool_assert_comparison)]
+fn test_is_katakana() {
+    assert_eq!(is_katakana("トマト"), true);
+    assert_eq!(is_katakana("トマト・パスト"), true);
+    assert_eq!(is_katakana("ﾄﾏﾄ"), false);
+    assert_eq!(is_katakana("とまと"), false);
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(to_full_width("ｶﾞｯｺｳ ﾊﾟﾝ ｳﾞｧ ｱﾞ"), "ガッコウ パン ヴァ ア゛");
+    assert_eq!(to_hiragana("ヴァイオリン・ケース"), "ゔぁいおりん・けーす");
+    assert_eq!(normalize("コーヒー"), "こおひい");
+    assert_eq!(normalize("ﾌｧｲﾙ"), "ふあいる");
+    assert_eq!(normalize("きゃー"), "きゃあ");
+    assert_eq!(normalize("ーあ"), "ーあ");
+}
+
+#[test]
+fn test_to_romaji() {
+    let romaji = |kana: &str, romanization| to_romaji(&to_hiragana(kana), romanization);
+    assert_eq!(romaji("しんぶん", Romanization::Hepburn), "shinbun");
+    assert_eq!(romaji("しんぶん", Romanization::Kunrei), "sinbun");
+    assert_eq!(romaji("ちゃっと", Romanization::Hepburn), "chatto");
+    assert_eq!(romaji("ちゃっと", Romanization::Kunrei), "tyatto");
+    assert_eq!(romaji("まっちゃ", Romanization::Hepburn), "matcha");
+    assert_eq!(romaji("つづく", Romanization::Kunrei), "tuzuku");
+    assert_eq!(romaji("フィルター", Romanization::Hepburn), "firutaa");
+    assert_eq!(romaji("ジェット", Romanization::Kunrei), "zyetto");
+    assert_eq!(romaji("あっ", Romanization::Hepburn), "a");
+}
+
+#[test]
+fn test_search_keys() {
+    assert_eq!(
+        search_keys("とうきょう", false),
+        ["tokyo", "toukyou", "とうきょう"]
+    );
+    assert_eq!(
+        search_keys("ﾁｰｽﾞ", false),
+        ["chiizu", "chizu", "tiizu", "ちいず"]
+    );
+
+    // Only long vowels are shortened
+    assert_eq!(search_keys("こうし", false), ["koshi", "koushi", "kousi", "こうし"]);
+    assert_eq!(search_keys("こし", false), ["koshi", "kosi", "こし"]);
+    assert_eq!(search_keys("おもう", true), ["omou", "おもう"]);
+    assert_eq!(search_keys("おもう", false), ["omo", "omou", "おもう"]);
+    assert_eq!(search_keys("ソウル", false), ["souru", "そうる"]);
+}
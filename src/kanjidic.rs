@@ -0,0 +1,380 @@
+//! Parser for KANJIDIC2, the EDRDG's kanji dictionary.
+//!
+//! Use [`CharacterReader`](struct.CharacterReader.html) to read
+//! [`Character`](struct.Character.html) values one at a time from a KANJIDIC2 document.
+
+use diagnostic::{Diagnostic, DiagnosticKind, DiagnosticSink, StderrSink};
+use failure::{Error, ResultExt};
+use input;
+use input::{PositionTracker, Positions};
+use parser::ParseError;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+
+/// character from kanjidic2 schema
+///
+/// Like `Entry`, this serializes using its field names, omitting empty lists and absent values.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Character {
+    /// literal
+    pub literal: String,
+    /// cp_value, keyed by cp_type, e.g. "ucs" => "4e9c"
+    pub codepoints: BTreeMap<String, String>,
+    /// rad_value, keyed by rad_type, e.g. "classical" => 7
+    pub radicals: BTreeMap<String, u8>,
+    /// The first stroke_count, which is the accepted count
+    pub stroke_count: u8,
+    /// Any further stroke_count values, which are common miscounts
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stroke_miscounts: Vec<u8>,
+    /// grade
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grade: Option<u8>,
+    /// freq
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<u16>,
+    /// jlpt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jlpt: Option<u8>,
+    /// reading, keyed by r_type, e.g. "ja_on" => ["ア"]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub readings: BTreeMap<String, Vec<String>>,
+    /// meaning, keyed by m_lang where meanings without a language are English ("en")
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meanings: BTreeMap<String, Vec<String>>,
+    /// nanori
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nanori: Vec<String>,
+}
+
+/// The elements of a character that we skip without reporting them.
+const IGNORED_ELEMENTS: &[&[u8]] = &[b"dic_number", b"query_code", b"variant", b"rad_name"];
+
+/// Reads characters one at a time from a KANJIDIC2 XML document.
+pub struct CharacterReader<T: BufRead> {
+    reader: Reader<PositionTracker<T>>,
+    positions: Positions,
+    buf: Vec<u8>,
+    sink: Box<dyn DiagnosticSink>,
+    done: bool,
+}
+
+impl CharacterReader<Box<dyn BufRead>> {
+    /// Read from the specified file, which may be gzip-compressed, or stdin if the path is "-".
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CharacterReader<Box<dyn BufRead>>, Error> {
+        Ok(CharacterReader::new(input::open(path)?))
+    }
+}
+
+impl<T: BufRead> CharacterReader<T> {
+    pub fn new(input: T) -> CharacterReader<T> {
+        let (input, positions) = PositionTracker::new(input);
+        let mut reader = Reader::from_reader(input);
+        reader.trim_text(true);
+        reader.expand_empty_elements(true);
+
+        CharacterReader {
+            reader,
+            positions,
+            buf: Vec::new(),
+            sink: Box::new(StderrSink),
+            done: false,
+        }
+    }
+
+    /// Send warnings about elements we don't recognize to `sink` instead of printing them to
+    /// stderr.
+    pub fn diagnostic_sink<S: DiagnosticSink + 'static>(
+        &mut self,
+        sink: S,
+    ) -> &mut CharacterReader<T> {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    fn read_character(&mut self) -> Result<Option<Character>, Error> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf) {
+                Ok(Event::Start(ref e)) if e.name() == b"character" => {
+                    let start = self.reader.buffer_position().saturating_sub(e.len() + 2);
+                    self.positions.mark(start);
+                    let mut literal = None;
+                    let result = parse_character(
+                        &mut self.reader,
+                        &self.positions,
+                        &mut *self.sink,
+                        &mut literal,
+                    );
+                    return match result {
+                        Ok(character) => Ok(Some(character)),
+                        Err(e) => Err(self.error(e, literal)),
+                    };
+                }
+                Ok(Event::Eof) => return Ok(None),
+                Err(e) => return Err(self.error(format_err!("Error parsing XML: {}", e), None)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Annotate `error` with the current position and the literal of the character being read.
+    fn error(&self, error: Error, literal: Option<String>) -> Error {
+        let message = match literal {
+            Some(literal) => format!("{} in character {}", error, literal),
+            None => error.to_string(),
+        };
+        ParseError {
+            message,
+            entry: None,
+            position: self.positions.position(self.reader.buffer_position()),
+        }.into()
+    }
+}
+
+impl<T: BufRead> Iterator for CharacterReader<T> {
+    type Item = Result<Character, Error>;
+
+    fn next(&mut self) -> Option<Result<Character, Error>> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_character() {
+            Ok(Some(character)) => Some(Ok(character)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Parse the contents of a character element, setting `literal` as soon as it is known so that it
+/// can be used to report any errors.
+fn parse_character<T: BufRead>(
+    reader: &mut Reader<T>,
+    positions: &Positions,
+    sink: &mut dyn DiagnosticSink,
+    literal: &mut Option<String>,
+) -> Result<Character, Error> {
+    let mut character = Character::default();
+    let mut stroke_counts: Vec<u8> = Vec::new();
+
+    enum Elem {
+        Literal,
+        Codepoint(String),
+        Radical(String),
+        Grade,
+        StrokeCount,
+        Frequency,
+        Jlpt,
+        Reading(String),
+        Meaning(String),
+        Nanori,
+    }
+    let mut elem: Option<Elem> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"literal" => elem = Some(Elem::Literal),
+                b"cp_value" => {
+                    elem = Some(Elem::Codepoint(required_attribute(e, b"cp_type", reader)?))
+                }
+                b"rad_value" => {
+                    elem = Some(Elem::Radical(required_attribute(e, b"rad_type", reader)?))
+                }
+                b"grade" => elem = Some(Elem::Grade),
+                b"stroke_count" => elem = Some(Elem::StrokeCount),
+                b"freq" => elem = Some(Elem::Frequency),
+                b"jlpt" => elem = Some(Elem::Jlpt),
+                b"reading" => {
+                    elem = Some(Elem::Reading(required_attribute(e, b"r_type", reader)?))
+                }
+                b"meaning" => {
+                    let lang = attribute(e, b"m_lang", reader)?.unwrap_or_else(|| "en".to_owned());
+                    elem = Some(Elem::Meaning(lang));
+                }
+                b"nanori" => elem = Some(Elem::Nanori),
+                // Containers for the elements above
+                b"codepoint" | b"radical" | b"misc" | b"reading_meaning" | b"rmgroup" => (),
+                // Dictionary references, query codes, variants etc. which we don't use, along with
+                // anything we don't recognize
+                name => {
+                    if !IGNORED_ELEMENTS.contains(&name) {
+                        let mut message = format!(
+                            "Unrecognized character member element {}",
+                            String::from_utf8_lossy(name)
+                        );
+                        if let Some(ref literal) = *literal {
+                            message += &format!(" in character {}", literal);
+                        }
+                        let position = positions.position(reader.buffer_position());
+                        let kind = DiagnosticKind::UnknownElement;
+                        sink.report(Diagnostic::new(kind, message, None, position))?;
+                    }
+                    let name = name.to_vec();
+                    reader
+                        .read_to_end(name, &mut Vec::new())
+                        .map_err(|e| format_err!("Error parsing XML: {}", e))?;
+                }
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"character" => break,
+                _ => elem = None,
+            },
+            Ok(Event::Text(e)) => {
+                let text = e.unescape_and_decode(reader)?;
+                match elem {
+                    Some(Elem::Literal) => {
+                        character.literal = text.clone();
+                        *literal = Some(text);
+                    }
+                    Some(Elem::Codepoint(ref cp_type)) => {
+                        character.codepoints.insert(cp_type.clone(), text);
+                    }
+                    Some(Elem::Radical(ref rad_type)) => {
+                        let value = u8::from_str(&text).context("Invalid rad_value")?;
+                        character.radicals.insert(rad_type.clone(), value);
+                    }
+                    Some(Elem::Grade) => {
+                        character.grade = Some(u8::from_str(&text).context("Invalid grade")?)
+                    }
+                    Some(Elem::StrokeCount) => {
+                        stroke_counts.push(u8::from_str(&text).context("Invalid stroke_count")?)
+                    }
+                    Some(Elem::Frequency) => {
+                        character.frequency = Some(u16::from_str(&text).context("Invalid freq")?)
+                    }
+                    Some(Elem::Jlpt) => {
+                        character.jlpt = Some(u8::from_str(&text).context("Invalid jlpt")?)
+                    }
+                    Some(Elem::Reading(ref r_type)) => character
+                        .readings
+                        .entry(r_type.clone())
+                        .or_default()
+                        .push(text),
+                    Some(Elem::Meaning(ref lang)) => character
+                        .meanings
+                        .entry(lang.clone())
+                        .or_default()
+                        .push(text),
+                    Some(Elem::Nanori) => character.nanori.push(text),
+                    None => bail!("Unexpected text \"{}\"", text),
+                }
+            }
+            Ok(Event::Eof) => bail!("Unexpected end of file"),
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    ensure!(!character.literal.is_empty(), "Character has no literal");
+    ensure!(!stroke_counts.is_empty(), "Character has no stroke_count");
+    character.stroke_count = stroke_counts.remove(0);
+    character.stroke_miscounts = stroke_counts;
+
+    Ok(character)
+}
+
+fn attribute<T: BufRead>(
+    start: &BytesStart,
+    name: &[u8],
+    reader: &Reader<T>,
+) -> Result<Option<String>, Error> {
+    for attr in start.attributes() {
+        let attr = attr?;
+        if attr.key == name {
+            return Ok(Some(attr.unescape_and_decode_value(reader)?));
+        }
+    }
+    Ok(None)
+}
+
+fn required_attribute<T: BufRead>(
+    start: &BytesStart,
+    name: &[u8],
+    reader: &Reader<T>,
+) -> Result<String, Error> {
+    match attribute(start, name, reader)? {
+        Some(value) => Ok(value),
+        None => bail!(
+            "Missing {} attribute on {}",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(start.name())
+        ),
+    }
+}
+
+#[test]
+fn test_character_reader() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let characters: Vec<Character> = CharacterReader::from_file("data/kanjidic2-sample.xml")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(characters.len(), 4);
+
+    let a = &characters[0];
+    assert_eq!(a.literal, "亜");
+    assert_eq!(a.codepoints["ucs"], "4e9c");
+    assert_eq!(a.radicals["classical"], 7);
+    assert_eq!(a.stroke_count, 7);
+    assert_eq!(a.grade, Some(8));
+    assert_eq!(a.frequency, Some(1509));
+    assert_eq!(a.jlpt, Some(1));
+    assert_eq!(a.readings["ja_on"], ["ア"]);
+    assert_eq!(a.readings["ja_kun"], ["つ.ぐ"]);
+    assert_eq!(a.meanings["en"], ["Asia", "rank next", "come after", "-ous"]);
+    assert_eq!(a.meanings["fr"], ["Asie", "suivant"]);
+    assert_eq!(a.nanori, ["や", "つぎ", "つぐ"]);
+
+    let oshi = &characters[1];
+    assert_eq!(oshi.stroke_count, 10);
+    assert_eq!(oshi.stroke_miscounts, [11]);
+    assert_eq!(oshi.grade, None);
+    assert_eq!(oshi.readings["ja_on"], ["ア", "アク"]);
+
+    let repeat = &characters[3];
+    assert_eq!(repeat.literal, "々");
+    assert!(repeat.readings.is_empty());
+    assert!(repeat.meanings.is_empty());
+
+    let mut reader = CharacterReader::new(
+        "<kanjidic2>\n<character><literal>亜</literal><misc><grade>x</grade></misc></character>"
+            .as_bytes(),
+    );
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Invalid grade in character 亜 at line 2, column 47"
+    );
+    assert!(reader.next().is_none());
+
+    let sink = Rc::new(RefCell::new(Vec::new()));
+    let mut reader = CharacterReader::new(
+        "<kanjidic2>\n<character><literal>亜</literal><misc><stroke_count>7</stroke_count>\
+         <variant var_type=\"jis208\">1-48-19</variant><unknown>x</unknown></misc></character>"
+            .as_bytes(),
+    );
+    reader.diagnostic_sink(sink.clone());
+    assert_eq!(reader.next().unwrap().unwrap().stroke_count, 7);
+    assert_eq!(sink.borrow().len(), 1);
+    assert_eq!(sink.borrow()[0].kind, DiagnosticKind::UnknownElement);
+    assert_eq!(
+        sink.borrow()[0].to_string(),
+        "Unrecognized character member element unknown in character 亜 at line 2, column 121"
+    );
+}
@@ -0,0 +1,37 @@
+//! Parser for the JMDict Japanese-multilingual dictionary XML file.
+//!
+//! Use [`EntryReader`](struct.EntryReader.html) to read [`Entry`](struct.Entry.html) values one at
+//! a time from a JMDict document, or [`NameReader`](struct.NameReader.html) to read
+//! [`NameEntry`](struct.NameEntry.html) values from a JMnedict document.
+
+#[macro_use]
+extern crate failure;
+extern crate flate2;
+extern crate memchr;
+extern crate quick_xml;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+extern crate smallvec;
+
+pub mod diagnostic;
+pub mod dtd;
+pub mod entities;
+mod entry;
+pub mod input;
+pub mod kana;
+pub mod kanjidic;
+mod parser;
+pub mod priority;
+mod release;
+mod xref;
+
+pub use entry::{CrossReference, Entry, Gloss, GlossType, InfoVec, KanjiEntry, LangSource, NameEntry,
+                PriorityVec, ReadingEntry, Sense, Translation};
+pub use parser::{parse_cross_ref, Dictionary, EntryReader, NameReader, ParseError};
+pub use release::ReleaseInfo;
+pub use xref::{resolve_cross_refs, XrefProblem, XrefProblemKind};
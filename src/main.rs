@@ -1,683 +1,430 @@
 #[macro_use]
 extern crate failure;
-extern crate memchr;
-extern crate quick_xml;
-extern crate smallvec;
+extern crate jmdict;
+extern crate rusqlite;
+extern crate serde;
 #[macro_use]
+extern crate serde_json;
 extern crate structopt;
-
+extern crate ureq;
+extern crate zip;
+
+mod couch;
+mod edict;
+mod export;
+mod report;
+mod sqlite;
+mod views;
+mod yomitan;
+#[cfg(test)]
+mod test_server;
+
+use export::{JsonFormat, OutputFormat};
 use failure::{Error, ResultExt};
-use smallvec::SmallVec;
-use std::path::PathBuf;
-use std::str;
+use jmdict::diagnostic::DiagnosticSink;
+use jmdict::kanjidic::{Character, CharacterReader};
+use jmdict::{Dictionary, Entry, EntryReader, NameEntry, NameReader, ReleaseInfo};
+use report::Reporter;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
 use structopt::StructOpt;
-use quick_xml::reader::Reader;
-use quick_xml::events::{BytesText, Event};
 
-#[derive(StructOpt)]
-#[structopt(name = "jmdict-couch")]
-/// Perform an incremental update of a CouchDB representation of the JMDict database using the
-/// supplied JMDict XML file.
-struct Opt {
-    #[structopt(short = "i", long = "input", help = "Input file", parse(from_os_str))]
-    input: PathBuf,
+/// The EDRDG dictionary files we can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InputFormat {
+    /// JMdict or JMnedict, as determined by the root element
+    Jmdict,
+    Kanjidic,
 }
 
-type InfoVec = SmallVec<[String; 4]>;
-type PriorityVec = SmallVec<[String; 4]>;
-
-/// entry from jmdict schema
-#[derive(Debug)]
-struct Entry {
-    /// ent_seq
-    id: u32,
-    /// k_ele children
-    kanji_entries: Vec<KanjiEntry>,
-    /// r_ele children
-    reading_entries: Vec<ReadingEntry>,
-    /// sense children
-    senses: Vec<Sense>,
-}
-
-/// k_ele from jmdict schema
-#[derive(Debug)]
-struct KanjiEntry {
-    /// keb
-    kanji: String,
-    /// ke_inf
-    info: InfoVec,
-    /// ke_pri
-    priority: PriorityVec,
-}
-
-/// r_ele from jmdict schema
-#[derive(Debug)]
-struct ReadingEntry {
-    /// reb
-    kana: String,
-    /// re_nokanji
-    no_kanji: bool,
-    /// re_restr
-    related_kanji: Vec<String>,
-    /// re_inf
-    info: InfoVec,
-    /// re_pri
-    priority: PriorityVec,
-}
-
-/// sense from jmdict schema
-#[derive(Debug, PartialEq)]
-struct Sense {
-    /// stagk
-    only_kanji: Vec<String>,
-    /// stagr
-    only_readings: Vec<String>,
-    /// pos
-    part_of_speech: Vec<String>,
-    /// xref
-    cross_refs: Vec<CrossReference>,
-    /// ant
-    antonyms: Vec<CrossReference>,
-    /// field
-    field: Vec<String>,
-    /// misc
-    misc: Vec<String>,
-    // s_inf
-    // sense_info: Option<String>,
-    // lsource
-    // lang_sources: Vec<LangSource>,
-    // dial
-    // dialect: Option<String>,
-    /// gloss
-    glosses: Vec<String>,
-
-    /// The language of this sense.
-    /// In JMDict this is annotated onto each gloss, but all glosses for a given sense have the same
-    /// language so we move this to the sense because it's more compact and allows us to create
-    /// per-language views more easily.
-    lang: Option<String>,
-}
+impl FromStr for InputFormat {
+    type Err = Error;
 
-#[derive(Debug, PartialEq)]
-struct CrossReference {
-    kanji_or_reading: String,
-    reading: Option<String>,
-    sense_index: Option<u8>,
+    fn from_str(format: &str) -> Result<InputFormat, Error> {
+        match format {
+            "jmdict" => Ok(InputFormat::Jmdict),
+            "kanjidic" => Ok(InputFormat::Kanjidic),
+            _ => bail!("Unrecognized input format \"{}\" (expected jmdict or kanjidic)", format),
+        }
+    }
 }
 
-/*
-struct LangSource {
-    lang: String,
-    original: Option<String>,
+#[derive(StructOpt)]
+#[structopt(name = "jmdict-couch")]
+/// Perform an incremental update of a CouchDB representation of the JMDict database using the
+/// supplied JMDict XML file (or JMnedict file, or KANJIDIC2 file with --input-format kanjidic).
+struct Opt {
+    #[structopt(short = "i", long = "input",
+                help = "Input file, optionally gzip-compressed, or - to read from stdin",
+                parse(from_os_str))]
+    input: PathBuf,
+    #[structopt(long = "input-format",
+                help = "The dictionary the input file contains (jmdict or kanjidic). JMnedict \
+                        files are detected automatically when using jmdict.",
+                default_value = "jmdict", parse(try_from_str))]
+    input_format: InputFormat,
+    #[structopt(long = "couch-url", help = "CouchDB server URL",
+                default_value = "http://localhost:5984")]
+    couch_url: String,
+    #[structopt(long = "database",
+                help = "CouchDB database name (default: jmdict, jmnedict or kanjidic depending on \
+                        the input)")]
+    database: Option<String>,
+    #[structopt(short = "o", long = "output",
+                help = "Write entries in the given format (json, ndjson, sqlite, yomitan or \
+                        edict2) instead of syncing to CouchDB",
+                parse(try_from_str))]
+    output: Option<OutputFormat>,
+    #[structopt(long = "output-file",
+                help = "File to write to when using --output or --check (default: stdout, \
+                        which is not supported for sqlite or yomitan)",
+                parse(from_os_str))]
+    output_file: Option<PathBuf>,
+    #[structopt(long = "lang",
+                help = "Only include senses in these languages, e.g. eng,ger (default: all, or \
+                        eng for yomitan and edict2)",
+                raw(use_delimiter = "true"))]
+    languages: Vec<String>,
+    #[structopt(long = "split-languages",
+                help = "Write a shared document for each entry's kanji and readings plus one \
+                        document per language for its senses")]
+    split_languages: bool,
+    #[structopt(long = "check",
+                help = "Check the input for problems and write them as a JSON report instead of \
+                        syncing to CouchDB")]
+    check: bool,
+    #[structopt(long = "diagnostics-file",
+                help = "Write warnings to this file as JSON, one per line, instead of stderr",
+                parse(from_os_str))]
+    diagnostics_file: Option<PathBuf>,
+    #[structopt(long = "strict",
+                help = "Stop at the first warning in the input, including unrecognized KANJIDIC \
+                        elements (with --check, fail at the end if there were any)")]
+    strict: bool,
+    #[structopt(long = "expanded-entities",
+                help = "Read input whose entities have already been expanded, e.g. by xmllint \
+                        --noent")]
+    expanded_entities: bool,
+    #[structopt(long = "allow-downgrade",
+                help = "Sync even if the database was synced from a newer release")]
+    allow_downgrade: bool,
 }
-*/
 
 fn main() {
     let opt = Opt::from_args();
 
-    let entries = get_entries(&opt.input);
-    if let Err(ref e) = entries {
-        use std::io::Write;
+    if let Err(ref e) = run(&opt) {
         let stderr = &mut ::std::io::stderr();
         writeln!(stderr, "{}", e).expect("Error writing to stderr");
         ::std::process::exit(1);
     }
-
-    let entries = entries.unwrap();
-
-    /*
-    for entry in entries {
-        println!("> {:?}", entry);
-    }
-    */
-    println!("Parsed {} entries", entries.len());
 }
 
-fn get_entries(input: &PathBuf) -> Result<Vec<Entry>, Error> {
-    let mut reader = Reader::from_file(input).context("Could not read from file")?;
-    reader.trim_text(true);
-    reader.check_end_names(false);
-    reader.expand_empty_elements(true);
-
-    let mut buf = Vec::new();
-    let mut entries: Vec<Entry> = Vec::new();
-
-    loop {
-        match reader.read_event(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name() {
-                b"entry" => {
-                    entries.push(parse_entry(&mut reader)?);
-                }
-                _ => (),
-            },
-            Ok(Event::Eof) => break,
-            Err(e) => bail!(
-                "Error parsing entry at position #{}: {}",
-                reader.buffer_position(),
-                e
-            ),
-            _ => (),
+fn run(opt: &Opt) -> Result<(), Error> {
+    match opt.input_format {
+        InputFormat::Jmdict => {
+            let mut reader = EntryReader::from_file(&opt.input)?;
+            if opt.expanded_entities {
+                reader.expanded_entities();
+            }
+            match reader.dictionary()? {
+                Dictionary::JMdict => run_jmdict(opt, reader),
+                Dictionary::JMnedict => run_jmnedict(opt, reader.names()),
+            }
         }
-        buf.clear();
+        InputFormat::Kanjidic => run_kanjidic(opt),
     }
-
-    Ok(entries)
 }
 
-fn parse_entry<T: std::io::BufRead>(reader: &mut Reader<T>) -> Result<Entry, Error> {
-    let mut id: u32 = 0;
-    let mut kanji_entries: Vec<KanjiEntry> = Vec::new();
-    let mut reading_entries: Vec<ReadingEntry> = Vec::new();
-    let mut senses: Vec<Sense> = Vec::new();
-
-    let mut buf = Vec::new();
-    let mut ent_seq = false;
-
-    loop {
-        match reader.read_event(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name() {
-                b"ent_seq" => {
-                    ensure!(
-                        !ent_seq,
-                        "Nested ent_seq at position #{}",
-                        reader.buffer_position()
-                    );
-                    ent_seq = true;
-                }
-                b"k_ele" => kanji_entries.push(parse_k_ele(reader)?),
-                b"r_ele" => reading_entries.push(parse_r_ele(reader)?),
-                b"sense" => senses.push(parse_sense(reader)?),
-                _ => warn_unknown_tag(e.name(), reader.buffer_position(), "entry"),
-            },
-            Ok(Event::End(ref e)) => match e.name() {
-                b"entry" => break,
-                b"ent_seq" => {
-                    ensure!(
-                        ent_seq,
-                        "Mismatched ent_seq tags at position #{}",
-                        reader.buffer_position()
-                    );
-                    ent_seq = false;
-                }
-                _ => (),
-            },
-            Ok(Event::Text(e)) => {
-                if ent_seq {
-                    id = u32::from_str(&e.unescape_and_decode(&reader)?)
-                        .context("Failed to parse ent_seq as int")?;
-                }
-            }
-            Err(e) => bail!(
-                "Error parsing entry at position #{}: {}",
-                reader.buffer_position(),
-                e
-            ),
-            _ => (),
-        }
-        buf.clear();
+fn run_jmdict(opt: &Opt, mut reader: EntryReader<Box<dyn BufRead>>) -> Result<(), Error> {
+    if !opt.languages.is_empty() {
+        reader.filter_languages(opt.languages.iter().map(String::as_str));
     }
 
-    ensure!(
-        id != 0,
-        "ID not found at position #{}",
-        reader.buffer_position()
-    );
-    ensure!(
-        !reading_entries.is_empty(),
-        "No reading entries found at position #{}",
-        reader.buffer_position()
-    );
-
-    Ok(Entry {
-        id,
-        kanji_entries,
-        reading_entries,
-        senses,
-    })
-}
+    if opt.check {
+        return check(opt, reader);
+    }
 
-fn parse_k_ele<T: std::io::BufRead>(reader: &mut Reader<T>) -> Result<KanjiEntry, Error> {
-    let mut kanji: String = String::new();
-    let mut info: InfoVec = InfoVec::new();
-    let mut priority: PriorityVec = PriorityVec::new();
+    let reporter = reporter(opt)?;
+    reader.diagnostic_sink(reporter.clone());
 
-    enum Elem {
-        Keb,
-        KeInf,
-        KePri,
+    // Cross-references can only be resolved once every entry has been read so every mode,
+    // including the JSON output, needs to collect all the entries first.
+    let mut entries = reader.by_ref().collect::<Result<Vec<Entry>, Error>>()?;
+    for problem in jmdict::resolve_cross_refs(&mut entries) {
+        reporter.borrow_mut().report(problem.into())?;
     }
-    let mut elem: Option<Elem> = None;
-    let mut buf = Vec::new();
-
-    loop {
-        match reader.read_event(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name() {
-                b"keb" => elem = Some(Elem::Keb),
-                b"ke_inf" => elem = Some(Elem::KeInf),
-                b"ke_pri" => elem = Some(Elem::KePri),
-                _ => warn_unknown_tag(e.name(), reader.buffer_position(), "k_ele"),
-            },
-            Ok(Event::End(ref e)) => match e.name() {
-                b"k_ele" => break,
-                _ => elem = None,
-            },
-            Ok(Event::Text(e)) => match elem {
-                Some(Elem::Keb) => kanji = e.unescape_and_decode(&reader)?,
-                Some(Elem::KeInf) => info.push(parse_single_entity(e.escaped(), reader)?),
-                Some(Elem::KePri) => priority.push(e.unescape_and_decode(&reader)?),
-                _ => warn_unexpected_text(&e, reader, "k_ele"),
-            },
-            Err(e) => bail!(
-                "Error parsing entry at position #{}: {}",
-                reader.buffer_position(),
-                e
-            ),
-            _ => (),
+    finish_reporting(&reporter)?;
+    let release = reader.release_info();
+
+    if let Some(format) = opt.output.and_then(OutputFormat::json_format) {
+        let out = open_output(opt)?;
+        let count = export::export_entries(entries.into_iter().map(Ok), format, out)?;
+        eprintln!("Wrote {} entries", count);
+        return Ok(());
+    }
+
+    match opt.output {
+        Some(OutputFormat::Sqlite) => {
+            sqlite::export_entries(&entries, output_path(opt, OutputFormat::Sqlite)?)?;
+            eprintln!("Wrote {} entries", entries.len());
+            return Ok(());
+        }
+        Some(OutputFormat::Yomitan) => {
+            let lang = single_language(opt, OutputFormat::Yomitan)?;
+            let path = output_path(opt, OutputFormat::Yomitan)?;
+            let file = File::create(path).context("Could not create output file")?;
+            let entities = reader.entities();
+            let count = yomitan::export_entries(&entries, entities, lang, &release, file)?;
+            eprintln!("Wrote {} terms from {} entries", count, entries.len());
+            return Ok(());
         }
-        buf.clear();
+        Some(OutputFormat::Edict2) => {
+            let lang = single_language(opt, OutputFormat::Edict2)?;
+            let count = edict::export_entries(&entries, lang, &release, open_output(opt)?)?;
+            eprintln!("Wrote {} entries", count);
+            return Ok(());
+        }
+        Some(OutputFormat::Json) | Some(OutputFormat::Ndjson) | None => (),
     }
 
-    assert!(
-        kanji.trim() == kanji,
-        "Kanji keys should not have leading or trailing whitespace"
+    let entities = reader.entities();
+    println!(
+        "Parsed {} entries and {} entity definitions",
+        entries.len(),
+        entities.len()
     );
-    ensure!(
-        !kanji.is_empty(),
-        "Kanji key is empty at position #{}",
-        reader.buffer_position()
+
+    let db = database(opt, "jmdict");
+    db.create_if_missing()?;
+    check_release(opt, &db, &release)?;
+    if couch::sync_entities(&db, entities)? {
+        println!("Updated entity definitions");
+    }
+    let design_doc_changes = views::sync_design_docs(&db, &entries)?;
+    if design_doc_changes > 0 {
+        println!("Updated {} design documents", design_doc_changes);
+    }
+    let (stats, unit) = if opt.split_languages {
+        (couch::sync_entries_by_language(&db, &entries)?, "documents")
+    } else {
+        (couch::sync_entries(&db, &entries)?, "entries")
+    };
+    println!(
+        "Added {} {unit}, modified {} {unit}, removed {} {unit}",
+        stats.added,
+        stats.modified,
+        stats.removed,
+        unit = unit
     );
+    sync_release(&db, &release)?;
 
-    Ok(KanjiEntry {
-        kanji,
-        info,
-        priority,
-    })
+    Ok(())
 }
 
-fn parse_r_ele<T: std::io::BufRead>(reader: &mut Reader<T>) -> Result<ReadingEntry, Error> {
-    let mut kana = String::new();
-    let mut no_kanji = false;
-    let mut related_kanji: Vec<String> = Vec::new();
-    let mut info: InfoVec = InfoVec::new();
-    let mut priority: PriorityVec = PriorityVec::new();
-
-    enum Elem {
-        Reb,
-        ReRestr,
-        ReInf,
-        RePri,
-    }
-    let mut elem: Option<Elem> = None;
-    let mut buf = Vec::new();
-
-    loop {
-        match reader.read_event(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name() {
-                b"reb" => elem = Some(Elem::Reb),
-                b"re_nokanji" => no_kanji = true,
-                b"re_restr" => elem = Some(Elem::ReRestr),
-                b"re_inf" => elem = Some(Elem::ReInf),
-                b"re_pri" => elem = Some(Elem::RePri),
-                _ => warn_unknown_tag(e.name(), reader.buffer_position(), "r_ele"),
-            },
-            Ok(Event::End(ref e)) => match e.name() {
-                b"r_ele" => break,
-                _ => elem = None,
-            },
-            Ok(Event::Text(e)) => match elem {
-                Some(Elem::Reb) => kana = e.unescape_and_decode(&reader).unwrap(),
-                Some(Elem::ReRestr) => related_kanji.push(e.unescape_and_decode(&reader).unwrap()),
-                Some(Elem::ReInf) => info.push(parse_single_entity(e.escaped(), reader)?),
-                Some(Elem::RePri) => priority.push(e.unescape_and_decode(&reader).unwrap()),
-                _ => warn_unexpected_text(&e, reader, "r_ele"),
-            },
-            Err(e) => bail!(
-                "Error parsing entry at position #{}: {}",
-                reader.buffer_position(),
-                e
-            ),
-            _ => (),
-        }
-        buf.clear();
+fn run_jmnedict(opt: &Opt, mut reader: NameReader<Box<dyn BufRead>>) -> Result<(), Error> {
+    ensure!(
+        !opt.check && !opt.split_languages && opt.languages.is_empty(),
+        "--check, --lang and --split-languages are only supported for JMdict input"
+    );
+    let output = json_output(opt)?;
+
+    let reporter = reporter(opt)?;
+    reader.diagnostic_sink(reporter.clone());
+    let names = reader.by_ref().collect::<Result<Vec<NameEntry>, Error>>()?;
+    finish_reporting(&reporter)?;
+    let release = reader.release_info();
+
+    if let Some(format) = output {
+        let out = open_output(opt)?;
+        let count = export::export_docs(names.iter().map(couch::name_doc), format, out)?;
+        eprintln!("Wrote {} names", count);
+        return Ok(());
     }
 
-    assert!(
-        kana.trim() == kana,
-        "Kana keys should not have leading or trailing whitespace"
+    let entities = reader.entities();
+    println!(
+        "Parsed {} names and {} entity definitions",
+        names.len(),
+        entities.len()
     );
-    ensure!(
-        !kana.is_empty(),
-        "Kana key is empty at position #{}",
-        reader.buffer_position()
+
+    let db = database(opt, "jmnedict");
+    db.create_if_missing()?;
+    check_release(opt, &db, &release)?;
+    if couch::sync_entities(&db, entities)? {
+        println!("Updated entity definitions");
+    }
+    let stats = couch::sync_names(&db, &names)?;
+    println!(
+        "Added {} names, modified {} names, removed {} names",
+        stats.added, stats.modified, stats.removed
     );
+    sync_release(&db, &release)?;
 
-    Ok(ReadingEntry {
-        kana,
-        no_kanji,
-        related_kanji,
-        info,
-        priority,
-    })
+    Ok(())
 }
 
-fn parse_sense<T: std::io::BufRead>(reader: &mut Reader<T>) -> Result<Sense, Error> {
-    let mut only_kanji: Vec<String> = Vec::new();
-    let mut only_readings: Vec<String> = Vec::new();
-    let mut part_of_speech: Vec<String> = Vec::new();
-    let mut cross_refs: Vec<CrossReference> = Vec::new();
-    let mut antonyms: Vec<CrossReference> = Vec::new();
-    let mut field: Vec<String> = Vec::new();
-    let mut misc: Vec<String> = Vec::new();
-    let mut glosses: Vec<String> = Vec::new();
-    let mut lang: Option<String> = None;
-
-    enum Elem {
-        SenseTagKanji,
-        SenseTagReading,
-        PartOfSpeech,
-        CrossReference,
-        Antonym,
-        Field,
-        Misc,
-        Gloss,
-    }
-    let mut elem: Option<Elem> = None;
-    let mut buf = Vec::new();
-
-    loop {
-        match reader.read_event(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name() {
-                b"stagk" => elem = Some(Elem::SenseTagKanji),
-                b"stagr" => elem = Some(Elem::SenseTagReading),
-                b"pos" => elem = Some(Elem::PartOfSpeech),
-                b"xref" => elem = Some(Elem::CrossReference),
-                b"ant" => elem = Some(Elem::Antonym),
-                b"field" => elem = Some(Elem::Field),
-                b"misc" => elem = Some(Elem::Misc),
-                b"gloss" => {
-                    elem = Some(Elem::Gloss);
-                    for a in e.attributes() {
-                        if let Ok(attr) = a {
-                            if attr.key == "xml:lang".as_bytes() {
-                                // XXX Do proper error handling here
-                                let lang_str = (str::from_utf8(&(attr.value))?).to_owned();
-                                match lang {
-                                    Some(ref current_lang_str) => {
-                                        ensure!(*current_lang_str == lang_str,
-                                                "All glosses within a sense should use the same language");
-                                    }
-                                    _ => lang = Some(lang_str),
-                                };
-                            }
-                        }
-                    }
-                }
-                // _ => warn_unknown_tag(e.name(), reader.buffer_position(), "sense"),
-                _ => (),
-            },
-            Ok(Event::End(ref e)) => match e.name() {
-                b"sense" => break,
-                _ => elem = None,
-            },
-            Ok(Event::Text(e)) => match elem {
-                Some(Elem::SenseTagKanji) => {
-                    only_kanji.push(e.unescape_and_decode(&reader).unwrap())
-                }
-                Some(Elem::SenseTagReading) => {
-                    only_readings.push(e.unescape_and_decode(&reader).unwrap())
-                }
-                Some(Elem::PartOfSpeech) => {
-                    part_of_speech.push(parse_single_entity(e.escaped(), reader)?)
-                }
-                Some(Elem::CrossReference) => cross_refs.push(parse_cross_ref(
-                    &e.unescape_and_decode(&reader).unwrap(),
-                    reader.buffer_position(),
-                )?),
-                Some(Elem::Antonym) => antonyms.push(parse_cross_ref(
-                    &e.unescape_and_decode(&reader).unwrap(),
-                    reader.buffer_position(),
-                )?),
-                Some(Elem::Field) => {
-                    field.push(parse_single_entity(e.escaped(), reader)?)
-                }
-                Some(Elem::Misc) => {
-                    misc.push(parse_single_entity(e.escaped(), reader)?)
-                }
-                Some(Elem::Gloss) => glosses.push(e.unescape_and_decode(&reader).unwrap()),
-                // _ => warn_unexpected_text(&e, reader, "r_ele"),
-                _ => (),
-            },
-            Err(e) => bail!(
-                "Error parsing entry at position #{}: {}",
-                reader.buffer_position(),
-                e
-            ),
-            _ => (),
-        }
-        buf.clear();
+fn run_kanjidic(opt: &Opt) -> Result<(), Error> {
+    ensure!(
+        !opt.check && !opt.split_languages && opt.languages.is_empty(),
+        "--check, --lang and --split-languages are only supported for JMdict input"
+    );
+    let output = json_output(opt)?;
+    ensure!(
+        !opt.expanded_entities,
+        "--expanded-entities is only supported for JMdict and JMnedict input"
+    );
+
+    let reporter = reporter(opt)?;
+    let mut reader = CharacterReader::from_file(&opt.input)?;
+    reader.diagnostic_sink(reporter.clone());
+    let characters = reader.collect::<Result<Vec<Character>, Error>>()?;
+    finish_reporting(&reporter)?;
+
+    if let Some(format) = output {
+        let out = open_output(opt)?;
+        let docs = characters.iter().map(couch::character_doc);
+        let count = export::export_docs(docs, format, out)?;
+        eprintln!("Wrote {} characters", count);
+        return Ok(());
     }
 
-    Ok(Sense {
-        only_kanji,
-        only_readings,
-        part_of_speech,
-        cross_refs,
-        antonyms,
-        field,
-        misc,
-        glosses,
-        lang,
-    })
-}
+    println!("Parsed {} characters", characters.len());
 
-#[test]
-fn test_parse_sense() {
-    let xml = r#"<sense>
-                 <stagk>延べる</stagk>
-                 <stagk>伸べる</stagk>
-                 <gloss>to postpone</gloss>
-                 <gloss>to extend</gloss>
-                 </sense>"#;
-    let mut reader = Reader::from_str(xml);
-    let mut buf = Vec::new();
-    let _ = reader.read_event(&mut buf);
-    assert_eq!(
-        parse_sense(&mut reader).unwrap(),
-        Sense {
-            only_kanji: vec!["延べる".to_owned(), "伸べる".to_owned()],
-            only_readings: vec![],
-            antonyms: vec![],
-            part_of_speech: vec![],
-            cross_refs: vec![],
-            glosses: vec!["to postpone".to_owned(), "to extend".to_owned()],
-            lang: None,
-        }
+    let db = database(opt, "kanjidic");
+    db.create_if_missing()?;
+    let stats = couch::sync_characters(&db, &characters)?;
+    println!(
+        "Added {} characters, modified {} characters, removed {} characters",
+        stats.added, stats.modified, stats.removed
     );
-}
 
-/// Take a string like "&ent;" and return "ent".
-//
-// What I'd really like to do here is have something like:
-//
-// ```ignore
-// trait ParseEntity<E>: E {
-//   fn parse(src: &str) -> Result<E>;
-// }
-//
-// enum KanjiInflection {
-//   ... have the contents and impl of ParseEntity produced by a mako template from a simple
-//       list of strings...
-// }
-//
-// pub fn parse_single_entity<E>(raw: &[u8]) -> Result<E, Error> where E: ParseEntity<E>
-// {
-//   ... throws when the value doesn't match
-// }
-//
-// Then we wouldn't need to decode at all and we could just pass integers around. But setting up the
-// build system to run mako is probably overkill for this.
-fn parse_single_entity<T: std::io::BufRead>(
-    raw: &[u8],
-    reader: &mut Reader<T>,
-) -> Result<String, Error> {
-    // Check we start with &, end with ;, and have nothing inbetween.
-    if !raw.starts_with(b"&") || !raw.ends_with(b";") || memchr::memchr(b'&', &raw[1..]).is_some()
-        || memchr::memchr(b';', &raw[..raw.len() - 1]).is_some()
-    {
-        bail!(
-            "Error parsing entity at position #{}",
-            reader.buffer_position(),
-        )
-    }
+    Ok(())
+}
 
-    Ok(reader.decode(&raw[1..raw.len() - 1]).into_owned())
+/// The database specified by --couch-url and --database, or `default_name` if no database was
+/// specified.
+fn database(opt: &Opt, default_name: &str) -> couch::Database {
+    let name = opt.database.as_ref().map_or(default_name, String::as_str);
+    couch::Database::new(&opt.couch_url, name)
 }
 
-fn parse_cross_ref(input: &str, buffer_position: usize) -> Result<CrossReference, Error> {
-    if input.is_empty() {
-        bail!("Empty cross-reference at position #{}", buffer_position);
+/// Refuse to sync an older release over a newer one unless --allow-downgrade was given.
+fn check_release(opt: &Opt, db: &couch::Database, release: &ReleaseInfo) -> Result<(), Error> {
+    if opt.allow_downgrade {
+        return Ok(());
     }
+    couch::check_release(db, release)
+        .map_err(|e| format_err!("{} (use --allow-downgrade to sync anyway)", e))
+}
 
-    let parts: Vec<&str> = input.split('・').collect();
-
-    // Simple case, no separators
-    if parts.len() == 1 {
-        return Ok(CrossReference {
-            kanji_or_reading: input.to_owned(),
-            reading: None,
-            sense_index: None,
-        });
+/// Record the release once the sync has succeeded.
+fn sync_release(db: &couch::Database, release: &ReleaseInfo) -> Result<(), Error> {
+    if couch::sync_release(db, release)? {
+        match release.created {
+            Some(ref created) => println!("Updated release metadata ({})", created),
+            None => println!("Updated release metadata"),
+        }
     }
+    Ok(())
+}
 
-    // The middle dot can either be the separator of the kanji / reading / sense OR it can just be
-    // the regular separator in a katakana word.
-
-    // If the last part is an integer, assign the sense.
-    let sense_index: Option<u8> = parts.last().unwrap().parse::<u8>().ok();
-    let non_sense_parts = if sense_index.is_some() {
-        parts.len() - 1
-    } else {
-        parts.len()
-    };
-
-    // Assign the other parts depending on if we're likely looking at a katakana word or a regular
-    // entry.
-    let mut reading: Option<String> = None;
-    let kanji_or_reading = if is_katakana(parts.first().unwrap()) {
-        parts[0..non_sense_parts].join("・").to_owned()
-    } else {
-        if non_sense_parts > 2 {
-            bail!(
-                "Error parsing cross-reference at position #{}: {}",
-                buffer_position,
-                input,
-            );
-        }
-        // Assign the reading if we have one
-        if non_sense_parts == 2 {
-            reading = Some(parts[1].to_owned());
-        }
-        (*parts.first().unwrap()).to_owned()
+/// The reporter for the diagnostics produced while reading the input, as specified by
+/// --diagnostics-file. With --strict it fails on the first diagnostic, once it has written it.
+fn reporter(opt: &Opt) -> Result<Rc<RefCell<Reporter>>, Error> {
+    let mut reporter = match opt.diagnostics_file {
+        Some(ref path) => Reporter::file(path)?,
+        None => Reporter::stderr(),
     };
+    reporter.strict = opt.strict;
+    Ok(Rc::new(RefCell::new(reporter)))
+}
 
-    Ok(CrossReference {
-        kanji_or_reading,
-        reading,
-        sense_index,
-    })
+/// Flush and summarize the diagnostics once the input has been read.
+fn finish_reporting(reporter: &RefCell<Reporter>) -> Result<(), Error> {
+    let mut reporter = reporter.borrow_mut();
+    reporter.finish()?;
+    eprintln!("{}", reporter.summary());
+    Ok(())
 }
 
-#[test]
-fn test_parse_cross_ref() {
-    assert_eq!(
-        parse_cross_ref("集束", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "集束".to_owned(),
-            reading: None,
-            sense_index: None,
-        }
-    );
-    assert_eq!(
-        parse_cross_ref("因・2", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "因".to_owned(),
-            reading: None,
-            sense_index: Some(2),
-        }
-    );
-    assert_eq!(
-        parse_cross_ref("如何・どう", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "如何".to_owned(),
-            reading: Some("どう".to_owned()),
-            sense_index: None,
-        }
-    );
-    assert_eq!(
-        parse_cross_ref("何方・どちら・1", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "何方".to_owned(),
-            reading: Some("どちら".to_owned()),
-            sense_index: Some(1),
-        }
-    );
-    assert_eq!(
-        parse_cross_ref("ブロードノーズ・セブンギル・シャーク", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "ブロードノーズ・セブンギル・シャーク".to_owned(),
-            reading: None,
-            sense_index: None,
-        }
+/// Read the whole input, writing a report of any problems found to the output.
+///
+/// Fails if there are any errors or, with --strict, warnings.
+fn check(opt: &Opt, mut reader: EntryReader<Box<dyn BufRead>>) -> Result<(), Error> {
+    ensure!(
+        opt.diagnostics_file.is_none(),
+        "--diagnostics-file cannot be used with --check, which includes the diagnostics in its \
+         report"
     );
-    // I'm not sure if this actually exists, but it seems possible.
-    assert_eq!(
-        parse_cross_ref("カタカナ・コトバ・2", 0).unwrap(),
-        CrossReference {
-            kanji_or_reading: "カタカナ・コトバ".to_owned(),
-            reading: None,
-            sense_index: Some(2),
-        }
+    let reporter = Rc::new(RefCell::new(Reporter::collect()));
+    reader.diagnostic_sink(reporter.clone()).report_errors();
+    let mut entries = reader.by_ref().collect::<Result<Vec<Entry>, Error>>()?;
+    let count = entries.len();
+    let mut reporter = reporter.borrow_mut();
+    for problem in jmdict::resolve_cross_refs(&mut entries) {
+        reporter.report(problem.into())?;
+    }
+
+    let mut out = open_output(opt)?;
+    let report = json!({ "entries": count, "diagnostics": reporter.collected() });
+    serde_json::to_writer_pretty(&mut out, &report).context("Failed to write report")?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+
+    ensure!(
+        reporter.errors == 0 && (!opt.strict || reporter.warnings == 0),
+        "Found {} in {} entries",
+        reporter.summary(),
+        count
     );
+    eprintln!("Checked {} entries: {}", count, reporter.summary());
+    Ok(())
 }
 
-fn is_katakana(word: &str) -> bool {
-    word.chars().all(|c| c >= '\u{30a0}' && c <= '\u{30ff}')
+/// The JSON format specified by --output, failing if it is a format that is only supported for
+/// JMdict input. This is checked before reading the input so that we never create the output file
+/// for a format we can't write.
+fn json_output(opt: &Opt) -> Result<Option<JsonFormat>, Error> {
+    match opt.output {
+        Some(format) => match format.json_format() {
+            Some(format) => Ok(Some(format)),
+            None => bail!("--output {} is only supported for JMdict input", format),
+        },
+        None => Ok(None),
+    }
 }
 
-#[test]
-fn test_is_katakana() {
-    assert_eq!(is_katakana("トマト"), true);
-    assert_eq!(is_katakana("トマト・パスト"), true);
-    assert_eq!(is_katakana("ﾄﾏﾄ"), false);
-    assert_eq!(is_katakana("とまと"), false);
+/// The language specified by --lang, or English, for output formats that only include the senses
+/// in one language.
+fn single_language(opt: &Opt, format: OutputFormat) -> Result<&str, Error> {
+    match opt.languages.len() {
+        0 => Ok("eng"),
+        1 => Ok(&opt.languages[0]),
+        _ => bail!("--output {} only supports a single --lang", format),
+    }
 }
 
-fn warn_unknown_tag(elem_name: &[u8], buffer_position: usize, ancestor: &str) {
-    match str::from_utf8(elem_name) {
-        Ok(tag) => println!(
-            "WARNING: Unrecognized {} member element {} at position #{}",
-            ancestor, tag, buffer_position
-        ),
-        _ => println!(
-            "WARNING: Unrecognized {} member element (non-utf8) at position #{}",
-            ancestor, buffer_position
-        ),
+/// The file specified by --output-file, for output formats that can't be written to stdout.
+fn output_path(opt: &Opt, format: OutputFormat) -> Result<&Path, Error> {
+    match opt.output_file {
+        Some(ref path) => Ok(path),
+        None => bail!("--output {} requires --output-file", format),
     }
 }
 
-fn warn_unexpected_text<T: std::io::BufRead>(text: &BytesText, reader: &Reader<T>, ancestor: &str) {
-    match text.unescape_and_decode(reader) {
-        Ok(text) => println!(
-            "WARNING: Unexpected text \"{}\" in {} element at position #{}",
-            text,
-            ancestor,
-            reader.buffer_position(),
-        ),
-        _ => println!(
-            "WARNING: Unexpected text in {} element (non-utf8) at position #{}",
-            ancestor,
-            reader.buffer_position()
-        ),
-    }
+/// The file specified by --output-file, or stdout.
+fn open_output(opt: &Opt) -> Result<Box<dyn Write>, Error> {
+    Ok(match opt.output_file {
+        Some(ref path) => {
+            let file = File::create(path).context("Could not create output file")?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(::std::io::stdout()),
+    })
 }
@@ -0,0 +1,1596 @@
+use diagnostic::{check_entry, Diagnostic, DiagnosticKind, DiagnosticSink, Severity, StderrSink};
+use dtd;
+use dtd::{EntityTable, ExpansionTable};
+use entities::{Dialect, Entity, Field, KanjiInfo, Misc, NameType, PartOfSpeech, ReadingInfo};
+use entry::{CrossReference, Entry, Gloss, GlossType, InfoVec, KanjiEntry, LangSource, NameEntry,
+            PriorityVec, ReadingEntry, Sense, Translation};
+use failure::{Error, Fail, ResultExt};
+use input;
+use input::{HashingReader, InputHash, Position, PositionTracker, Positions};
+use kana::is_katakana;
+use memchr;
+use priority::Priority;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::reader::Reader;
+use release::ReleaseInfo;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::BufRead;
+use std::path::Path;
+use std::str;
+use std::str::FromStr;
+
+/// The dictionaries whose documents we can read, identified by their root element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dictionary {
+    /// JMdict, read using `EntryReader`
+    JMdict,
+    /// JMnedict, the dictionary of proper names, read using `NameReader`
+    JMnedict,
+}
+
+impl fmt::Display for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Dictionary::JMdict => "JMdict",
+            Dictionary::JMnedict => "JMnedict",
+        })
+    }
+}
+
+/// Reads entries one at a time from a JMDict XML document.
+///
+/// ```no_run
+/// # extern crate jmdict;
+/// # fn main() {
+/// let mut reader = jmdict::EntryReader::from_file("JMdict_e").unwrap();
+/// for entry in &mut reader {
+///     println!("{}", entry.unwrap().id);
+/// }
+/// println!("{} entity definitions", reader.entities().len());
+/// # }
+/// ```
+pub struct EntryReader<T: BufRead> {
+    reader: Reader<PositionTracker<HashingReader<T>>>,
+    positions: Positions,
+    hash: InputHash,
+    /// The release details gathered so far, apart from the hash
+    release: ReleaseInfo,
+    buf: Vec<u8>,
+    entities: EntityTable,
+    languages: Option<HashSet<String>>,
+    sink: Box<dyn DiagnosticSink>,
+    /// Whether to report errors to the sink rather than failing on them
+    report_errors: bool,
+    /// The ent_seq values seen so far, used to detect duplicates when reporting errors
+    ids: HashSet<u32>,
+    /// Set when reading a document whose entities have been expanded
+    expansions: Option<ExpansionTable>,
+    /// Set once we have read the root element
+    dictionary: Option<Dictionary>,
+    done: bool,
+}
+
+impl EntryReader<Box<dyn BufRead>> {
+    /// Read from the specified file, which may be gzip-compressed, or stdin if the path is "-".
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<EntryReader<Box<dyn BufRead>>, Error> {
+        Ok(EntryReader::new(input::open(path)?))
+    }
+}
+
+impl<T: BufRead> EntryReader<T> {
+    pub fn new(input: T) -> EntryReader<T> {
+        let (input, hash) = HashingReader::new(input);
+        let (input, positions) = PositionTracker::new(input);
+        let mut reader = Reader::from_reader(input);
+        reader.trim_text(true);
+        reader.check_end_names(false);
+        reader.expand_empty_elements(true);
+
+        EntryReader {
+            reader,
+            positions,
+            hash,
+            release: ReleaseInfo::default(),
+            buf: Vec::new(),
+            entities: EntityTable::new(),
+            languages: None,
+            sink: Box::new(StderrSink),
+            report_errors: false,
+            ids: HashSet::new(),
+            expansions: None,
+            dictionary: None,
+            done: false,
+        }
+    }
+
+    /// Send warnings and, if `report_errors` is in effect, errors to `sink` instead of printing
+    /// them to stderr.
+    pub fn diagnostic_sink<S: DiagnosticSink + 'static>(&mut self, sink: S) -> &mut EntryReader<T> {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    /// Report problems such as empty keys or mismatched restrictions to the diagnostic sink
+    /// instead of failing on them. This also enables the checks that are only performed when
+    /// reporting errors, i.e. `check_entry` and detecting duplicate ent_seq values.
+    pub fn report_errors(&mut self) -> &mut EntryReader<T> {
+        self.report_errors = true;
+        self
+    }
+
+    /// Read a document whose entity references have already been expanded, e.g. by
+    /// `xmllint --noent`, by mapping the text of elements such as pos back to entity codes using
+    /// the declarations in the DOCTYPE.
+    pub fn expanded_entities(&mut self) -> &mut EntryReader<T> {
+        self.expansions = Some(dtd::expansion_table(&self.entities));
+        self
+    }
+
+    /// Only return senses whose language is one of `languages` (e.g. "eng", "ger") where senses
+    /// without an explicit language are treated as English. Entries that are left with no senses
+    /// are skipped altogether.
+    pub fn filter_languages<I, S>(&mut self, languages: I) -> &mut EntryReader<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.languages = Some(languages.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The entity declarations from the DOCTYPE, e.g. "adj-i" => "adjective (keiyoushi)".
+    ///
+    /// The DOCTYPE precedes the entries so this is populated by the time the first entry is
+    /// returned.
+    pub fn entities(&self) -> &EntityTable {
+        &self.entities
+    }
+
+    /// The creation date and DTD revisions from the comments at the start of the document along
+    /// with the hash of the input and number of entries read so far.
+    ///
+    /// The hash and entry count are only complete once every entry has been read.
+    pub fn release_info(&self) -> ReleaseInfo {
+        ReleaseInfo {
+            input_hash: self.hash.hex(),
+            ..self.release.clone()
+        }
+    }
+
+    /// Which dictionary the document contains, according to its root element.
+    ///
+    /// This reads up to the root element if necessary so it can be called before reading any
+    /// entries to decide whether to read the document as it is or switch to a `NameReader` using
+    /// `names`.
+    pub fn dictionary(&mut self) -> Result<Dictionary, Error> {
+        if let Some(dictionary) = self.dictionary {
+            return Ok(dictionary);
+        }
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf) {
+                Ok(Event::DocType(ref e)) => {
+                    self.entities = dtd::parse_entity_declarations(&self.reader.decode(e.escaped()))
+                        .map_err(|e| self.error(e, None))?;
+                    if self.expansions.is_some() {
+                        self.expansions = Some(dtd::expansion_table(&self.entities));
+                    }
+                }
+                Ok(Event::Comment(ref e)) => {
+                    self.release.add_comment(&self.reader.decode(e.escaped()));
+                }
+                Ok(Event::Start(ref e)) => {
+                    let dictionary = match e.name() {
+                        b"JMdict" => Dictionary::JMdict,
+                        b"JMnedict" => Dictionary::JMnedict,
+                        name => {
+                            let name = String::from_utf8_lossy(name).into_owned();
+                            let error = format_err!("Unrecognized root element {}", name);
+                            return Err(self.error(error, None));
+                        }
+                    };
+                    self.dictionary = Some(dictionary);
+                    return Ok(dictionary);
+                }
+                Ok(Event::Eof) => return Err(self.error(format_err!("No root element"), None)),
+                Err(e) => return Err(self.error(format_err!("Error parsing XML: {}", e), None)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Read the rest of the document as JMnedict names, keeping the diagnostic sink and whether
+    /// errors are reported.
+    pub fn names(self) -> NameReader<T> {
+        NameReader { inner: self }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<Entry>, Error> {
+        while let Some((mut entry, start)) = self.read_next(Dictionary::JMdict, parse_entry)? {
+            if self.report_errors {
+                self.check_entry(&entry, start)?;
+            }
+            if let Some(ref languages) = self.languages {
+                entry
+                    .senses
+                    .retain(|sense| languages.contains(sense.language()));
+                if entry.senses.is_empty() {
+                    continue;
+                }
+            }
+            self.release.entry_count += 1;
+            return Ok(Some(entry));
+        }
+        Ok(None)
+    }
+
+    /// Parse the next entry element using `parse`, returning the result along with the byte
+    /// offset of the start of the element. Fails if the document is not a `dictionary` document.
+    fn read_next<E, F>(
+        &mut self,
+        dictionary: Dictionary,
+        parse: F,
+    ) -> Result<Option<(E, usize)>, Error>
+    where
+        F: Fn(&mut Reader<PositionTracker<HashingReader<T>>>, &mut EntryContext)
+            -> Result<E, Error>,
+    {
+        let found = self.dictionary()?;
+        if found != dictionary {
+            let error = format_err!("Expected a {} document but found {}", dictionary, found);
+            return Err(self.error(error, None));
+        }
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf) {
+                Ok(Event::Start(ref e)) if e.name() == b"entry" => {
+                    // Step back over the "<entry>" tag to report the position of its start
+                    let start = self.reader.buffer_position().saturating_sub(e.len() + 2);
+                    self.positions.mark(start);
+                    let mut context = EntryContext::new(&self.positions, &mut *self.sink);
+                    context.report_errors = self.report_errors;
+                    context.expansions = self.expansions.as_ref();
+                    return match parse(&mut self.reader, &mut context) {
+                        Ok(entry) => Ok(Some((entry, start))),
+                        Err(e) => {
+                            let id = context.id;
+                            Err(self.error(e, id))
+                        }
+                    };
+                }
+                Ok(Event::Eof) => return Ok(None),
+                Err(e) => return Err(self.error(format_err!("Error parsing XML: {}", e), None)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Annotate `error` with the current position and the ent_seq of the entry being read, if any.
+    fn error(&self, error: Error, entry: Option<u32>) -> Error {
+        ParseError {
+            message: error.to_string(),
+            entry,
+            position: self.positions.position(self.reader.buffer_position()),
+        }.into()
+    }
+
+    /// Report any inconsistencies within `entry`, which starts at byte offset `start`, and whether
+    /// its ent_seq has been used before.
+    fn check_entry(&mut self, entry: &Entry, start: usize) -> Result<(), Error> {
+        let position = self.positions.position(start);
+        for diagnostic in check_entry(entry, position) {
+            self.sink.report(diagnostic)?;
+        }
+        self.check_id(entry.id, start)
+    }
+
+    /// Report whether the ent_seq of the entry starting at byte offset `start` has been used
+    /// before.
+    fn check_id(&mut self, id: u32, start: usize) -> Result<(), Error> {
+        if !self.ids.insert(id) {
+            self.sink.report(Diagnostic::new(
+                DiagnosticKind::DuplicateId,
+                "Duplicate ent_seq".to_owned(),
+                Some(id),
+                self.positions.position(start),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: BufRead> Iterator for EntryReader<T> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Result<Entry, Error>> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.read_entry();
+        match result {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            // There's no sensible way to recover from an error so stop iterating.
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_entry_reader() {
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    let first = reader.next().unwrap().unwrap();
+    assert_eq!(first.id, 1_000_000);
+    assert_eq!(first.reading_entries[0].kana, "ヽ");
+    assert_eq!(reader.entities()["unc"], "unclassified");
+    assert_eq!(reader.count(), 12);
+
+    let mut reader = EntryReader::new("<JMdict>\n<entry><ent_seq>1</ent_seq></entry>".as_bytes());
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(
+        error.downcast::<ParseError>().unwrap(),
+        ParseError {
+            message: "No reading entries found".to_owned(),
+            entry: Some(1),
+            position: Position { line: 2, column: 36 },
+        }
+    );
+    assert!(reader.next().is_none());
+
+    let mut reader = EntryReader::new("<JMdict>\n<entry><ent_seq>x</ent_seq>".as_bytes());
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Failed to parse ent_seq as int at line 2, column 19"
+    );
+}
+
+#[test]
+fn test_release_info() {
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    reader.by_ref().count();
+    let release = reader.release_info();
+    assert_eq!(release.created, Some("2018-02-14".to_owned()));
+    assert_eq!(release.dtd_revisions, ["1.08", "1.07", "1.06", "1.05", "1.04"]);
+    assert_eq!(release.entry_count, 13);
+    assert_eq!(
+        release.input_hash,
+        "a9c3a2ddf309202d7d711303d47dd135528be6a45cf33537059bc545466c4799"
+    );
+}
+
+#[test]
+fn test_filter_languages() {
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    reader.filter_languages(vec!["eng", "ger"]);
+    let entries: Vec<Entry> = reader.collect::<Result<_, _>>().unwrap();
+    assert_eq!(entries.len(), 13);
+    assert!(entries
+        .iter()
+        .flat_map(|entry| entry.senses.iter())
+        .all(|sense| sense.lang.is_none() || sense.lang == Some("ger".to_owned())));
+    // 〃 has one English sense and one German sense
+    assert_eq!(entries[1].senses.len(), 2);
+
+    // Entries with no Hungarian senses should be dropped
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    reader.filter_languages(vec!["hun"]);
+    let entries: Vec<Entry> = reader.collect::<Result<_, _>>().unwrap();
+    assert!(entries.len() < 13);
+    assert!(entries
+        .iter()
+        .all(|entry| entry.senses.iter().all(|sense| sense.language() == "hun")));
+}
+
+#[test]
+fn test_report_errors() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let xml = r#"<JMdict>
+<entry><ent_seq>1</ent_seq>
+<k_ele><keb></keb></k_ele>
+<r_ele><reb>かな</reb><unknown/></r_ele>
+<sense><gloss xml:lang="ger">Kana</gloss><gloss xml:lang="fre">kana</gloss></sense>
+</entry>
+<entry><ent_seq>1</ent_seq>
+<r_ele><reb>かな</reb></r_ele>
+</entry>
+</JMdict>"#;
+
+    let mut reader = EntryReader::new(xml.as_bytes());
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Kanji key is empty in entry 1 at line 3, column 27"
+    );
+
+    // Warnings are still reported when errors are fatal
+    let sink = Rc::new(RefCell::new(Vec::new()));
+    let with_kanji = xml.replace("<keb></keb>", "<keb>仮名</keb>");
+    let mut reader = EntryReader::new(with_kanji.as_bytes());
+    reader.diagnostic_sink(sink.clone());
+    assert!(reader.next().unwrap().is_err());
+    assert_eq!(sink.borrow().len(), 1);
+    assert_eq!(sink.borrow()[0].kind, DiagnosticKind::UnknownElement);
+    assert_eq!(sink.borrow()[0].severity, Severity::Warning);
+
+    let sink = Rc::new(RefCell::new(Vec::new()));
+    let mut reader = EntryReader::new(xml.as_bytes());
+    reader.diagnostic_sink(sink.clone()).report_errors();
+    let entries: Vec<Entry> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].senses[0].lang, Some("ger".to_owned()));
+
+    let diagnostics: Vec<(DiagnosticKind, Option<u32>, usize)> = sink
+        .borrow()
+        .iter()
+        .map(|d| (d.kind, d.entry, d.position.unwrap().line))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (DiagnosticKind::EmptyKey, Some(1), 3),
+            (DiagnosticKind::UnknownElement, Some(1), 4),
+            (DiagnosticKind::MixedGlossLanguages, Some(1), 5),
+            (DiagnosticKind::DuplicateId, Some(1), 7),
+        ]
+    );
+    assert_eq!(sink.borrow()[3].position.unwrap().column, 1);
+}
+
+#[test]
+fn test_report_invalid_values() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let xml = r#"<JMdict>
+<entry><ent_seq>1</ent_seq>
+<r_ele><reb>かな</reb><re_pri>news9</re_pri><re_pri>ichi1</re_pri></r_ele>
+<sense><pos>&n;</pos><misc>&bogus;</misc><xref>仮・か・な</xref><lsource ls_type="all">kana</lsource>
+<gloss g_type="pun">kana</gloss></sense>
+</entry>
+</JMdict>"#;
+
+    let mut reader = EntryReader::new(xml.as_bytes());
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Unrecognized priority code \"news9\" in entry 1 at line 3, column 35"
+    );
+
+    let sink = Rc::new(RefCell::new(Vec::new()));
+    let mut reader = EntryReader::new(xml.as_bytes());
+    reader.diagnostic_sink(sink.clone()).report_errors();
+    let entry = reader.next().unwrap().unwrap();
+    assert_eq!(entry.reading_entries[0].priority.len(), 1);
+    let sense = &entry.senses[0];
+    assert_eq!(sense.part_of_speech.len(), 1);
+    assert!(sense.misc.is_empty());
+    assert!(sense.cross_refs.is_empty());
+    assert!(!sense.lang_sources[0].partial);
+    assert_eq!(sense.glosses[0].gloss_type, None);
+
+    let diagnostics: Vec<(DiagnosticKind, usize)> = sink
+        .borrow()
+        .iter()
+        .map(|d| (d.kind, d.position.unwrap().line))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (DiagnosticKind::InvalidValue, 3),
+            (DiagnosticKind::InvalidValue, 4),
+            (DiagnosticKind::InvalidValue, 4),
+            (DiagnosticKind::InvalidValue, 4),
+            (DiagnosticKind::InvalidValue, 5),
+        ]
+    );
+    assert_eq!(sink.borrow()[3].message, "Unexpected ls_type value \"all\"");
+}
+
+/// Reads names one at a time from a JMnedict XML document.
+///
+/// This supports the same options as `EntryReader` apart from `filter_languages`. Use
+/// `EntryReader::dictionary` and `EntryReader::names` to read documents that may contain either
+/// dictionary.
+pub struct NameReader<T: BufRead> {
+    inner: EntryReader<T>,
+}
+
+impl NameReader<Box<dyn BufRead>> {
+    /// Read from the specified file, which may be gzip-compressed, or stdin if the path is "-".
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<NameReader<Box<dyn BufRead>>, Error> {
+        Ok(NameReader::new(input::open(path)?))
+    }
+}
+
+impl<T: BufRead> NameReader<T> {
+    pub fn new(input: T) -> NameReader<T> {
+        EntryReader::new(input).names()
+    }
+
+    /// See `EntryReader::diagnostic_sink`.
+    pub fn diagnostic_sink<S: DiagnosticSink + 'static>(&mut self, sink: S) -> &mut NameReader<T> {
+        self.inner.diagnostic_sink(sink);
+        self
+    }
+
+    /// See `EntryReader::expanded_entities`.
+    pub fn expanded_entities(&mut self) -> &mut NameReader<T> {
+        self.inner.expanded_entities();
+        self
+    }
+
+    /// See `EntryReader::report_errors`. Only duplicate ent_seq values are checked in addition to
+    /// the errors detected while parsing.
+    pub fn report_errors(&mut self) -> &mut NameReader<T> {
+        self.inner.report_errors();
+        self
+    }
+
+    /// The entity declarations from the DOCTYPE, e.g. "surname" => "family or surname".
+    pub fn entities(&self) -> &EntityTable {
+        self.inner.entities()
+    }
+
+    /// See `EntryReader::release_info`.
+    pub fn release_info(&self) -> ReleaseInfo {
+        self.inner.release_info()
+    }
+
+    fn read_name(&mut self) -> Result<Option<NameEntry>, Error> {
+        match self.inner.read_next(Dictionary::JMnedict, parse_name_entry)? {
+            Some((name, start)) => {
+                if self.inner.report_errors {
+                    self.inner.check_id(name.id, start)?;
+                }
+                self.inner.release.entry_count += 1;
+                Ok(Some(name))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T: BufRead> Iterator for NameReader<T> {
+    type Item = Result<NameEntry, Error>;
+
+    fn next(&mut self) -> Option<Result<NameEntry, Error>> {
+        if self.inner.done {
+            return None;
+        }
+
+        match self.read_name() {
+            Ok(Some(name)) => Some(Ok(name)),
+            Ok(None) => {
+                self.inner.done = true;
+                None
+            }
+            Err(e) => {
+                self.inner.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_name_reader() {
+    let mut reader = EntryReader::from_file("data/jmnedict-sample.xml").unwrap();
+    assert_eq!(reader.dictionary().unwrap(), Dictionary::JMnedict);
+    assert_eq!(reader.entities()["surname"], "family or surname");
+    let names: Vec<NameEntry> = reader.names().collect::<Result<_, _>>().unwrap();
+    assert_eq!(names.len(), 5);
+
+    assert_eq!(names[0].id, 5_000_000);
+    assert_eq!(names[0].kanji_entries[0].kanji, "ゝ泉");
+    assert_eq!(names[0].reading_entries[0].kana, "こずみ");
+    assert_eq!(
+        names[0].translations,
+        [Translation {
+            name_type: vec![NameType::Surname],
+            cross_refs: vec![],
+            details: vec!["Kozumi".to_owned()],
+            lang: None,
+        }]
+    );
+
+    let tokyo = &names[2];
+    assert_eq!(tokyo.translations[0].name_type, [NameType::Place]);
+    assert_eq!(tokyo.translations[0].cross_refs[0].kanji_or_reading, "東京都");
+    assert_eq!(tokyo.translations[1].lang, Some("ger".to_owned()));
+
+    // Each reader only accepts its own dictionary
+    let mut reader = EntryReader::from_file("data/jmnedict-sample.xml").unwrap();
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Expected a JMdict document but found JMnedict at line 34, column 11"
+    );
+    let mut reader = NameReader::from_file("data/sample.xml").unwrap();
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Expected a JMnedict document but found JMdict at line 401, column 9"
+    );
+
+    let mut reader = EntryReader::new("<kanjidic2></kanjidic2>".as_bytes());
+    assert_eq!(
+        reader.dictionary().unwrap_err().to_string(),
+        "Unrecognized root element kanjidic2 at line 1, column 12"
+    );
+}
+
+/// An error reading a JMDict document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// The ent_seq of the entry containing the error, if it was known when the error occurred
+    pub entry: Option<u32>,
+    pub position: Position,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(entry) = self.entry {
+            write!(f, " in entry {}", entry)?;
+        }
+        write!(f, " at {}", self.position)
+    }
+}
+
+impl Fail for ParseError {}
+
+/// State shared by the functions that parse the parts of a single entry.
+struct EntryContext<'a> {
+    positions: &'a Positions,
+    sink: &'a mut dyn DiagnosticSink,
+    /// Whether to report errors to the sink rather than failing on them
+    report_errors: bool,
+    /// Used to look up entity codes when the document's entities have been expanded
+    expansions: Option<&'a ExpansionTable>,
+    /// The ent_seq of the entry once we have read it
+    id: Option<u32>,
+}
+
+impl<'a> EntryContext<'a> {
+    fn new(positions: &'a Positions, sink: &'a mut dyn DiagnosticSink) -> EntryContext<'a> {
+        EntryContext {
+            positions,
+            sink,
+            report_errors: false,
+            expansions: None,
+            id: None,
+        }
+    }
+
+    /// Report a problem at the current position. Errors fail unless we are reporting them.
+    fn report<T: BufRead>(
+        &mut self,
+        reader: &Reader<T>,
+        kind: DiagnosticKind,
+        message: String,
+    ) -> Result<(), Error> {
+        // The caller will add the position when it handles the error
+        if kind.severity() == Severity::Error && !self.report_errors {
+            return Err(format_err!("{}", message));
+        }
+
+        let position = self.positions.position(reader.buffer_position());
+        self.sink
+            .report(Diagnostic::new(kind, message, self.id, position))
+    }
+
+    /// Pass on a value we parsed or, if we are reporting errors, report why we could not parse it
+    /// and return None so that the caller skips it.
+    fn check_value<V, T: BufRead>(
+        &mut self,
+        reader: &Reader<T>,
+        value: Result<V, Error>,
+    ) -> Result<Option<V>, Error> {
+        match value {
+            Ok(value) => Ok(Some(value)),
+            Err(error) => self.report(reader, DiagnosticKind::InvalidValue, error.to_string())
+                .map(|_| None),
+        }
+    }
+}
+
+fn parse_entry<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<Entry, Error> {
+    let mut senses: Vec<Sense> = Vec::new();
+    let (id, kanji_entries, reading_entries) =
+        parse_entry_elements(reader, context, |name, reader, context| {
+            match name {
+                b"sense" => senses.push(parse_sense(reader, context)?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+    Ok(Entry {
+        id,
+        kanji_entries,
+        reading_entries,
+        senses,
+    })
+}
+
+fn parse_name_entry<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<NameEntry, Error> {
+    let mut translations: Vec<Translation> = Vec::new();
+    let (id, kanji_entries, reading_entries) =
+        parse_entry_elements(reader, context, |name, reader, context| {
+            match name {
+                b"trans" => translations.push(parse_trans(reader, context)?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+
+    Ok(NameEntry {
+        id,
+        kanji_entries,
+        reading_entries,
+        translations,
+    })
+}
+
+/// Parse the ent_seq, k_ele and r_ele elements shared by JMdict and JMnedict entries.
+///
+/// Any other child element is passed to `parse_child` which returns false if it does not
+/// recognize the element.
+#[allow(clippy::collapsible_match)]
+fn parse_entry_elements<T, F>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+    mut parse_child: F,
+) -> Result<(u32, Vec<KanjiEntry>, Vec<ReadingEntry>), Error>
+where
+    T: BufRead,
+    F: FnMut(&[u8], &mut Reader<T>, &mut EntryContext) -> Result<bool, Error>,
+{
+    let mut id: u32 = 0;
+    let mut kanji_entries: Vec<KanjiEntry> = Vec::new();
+    let mut reading_entries: Vec<ReadingEntry> = Vec::new();
+
+    let mut buf = Vec::new();
+    let mut ent_seq = false;
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"ent_seq" => {
+                    ensure!(!ent_seq, "Nested ent_seq");
+                    ent_seq = true;
+                }
+                b"k_ele" => kanji_entries.push(parse_k_ele(reader, context)?),
+                b"r_ele" => reading_entries.push(parse_r_ele(reader, context)?),
+                name => {
+                    if !parse_child(name, reader, context)? {
+                        warn_unknown_tag(name, reader, context, "entry")?;
+                    }
+                }
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"entry" => break,
+                b"ent_seq" => {
+                    ensure!(ent_seq, "Mismatched ent_seq tags");
+                    ent_seq = false;
+                }
+                _ => (),
+            },
+            Ok(Event::Text(e)) => {
+                if ent_seq {
+                    id = u32::from_str(&e.unescape_and_decode(reader)?)
+                        .context("Failed to parse ent_seq as int")?;
+                    context.id = Some(id);
+                }
+            }
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    ensure!(id != 0, "ID not found");
+    ensure!(!reading_entries.is_empty(), "No reading entries found");
+
+    Ok((id, kanji_entries, reading_entries))
+}
+
+fn parse_k_ele<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<KanjiEntry, Error> {
+    let mut kanji: String = String::new();
+    let mut info: InfoVec<KanjiInfo> = InfoVec::new();
+    let mut priority: PriorityVec = PriorityVec::new();
+
+    enum Elem {
+        Keb,
+        KeInf,
+        KePri,
+    }
+    let mut elem: Option<Elem> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"keb" => elem = Some(Elem::Keb),
+                b"ke_inf" => elem = Some(Elem::KeInf),
+                b"ke_pri" => elem = Some(Elem::KePri),
+                _ => warn_unknown_tag(e.name(), reader, context, "k_ele")?,
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"k_ele" => break,
+                _ => elem = None,
+            },
+            Ok(Event::Text(e)) => match elem {
+                Some(Elem::Keb) => kanji = e.unescape_and_decode(reader)?,
+                Some(Elem::KeInf) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        info.push(value)
+                    }
+                }
+                Some(Elem::KePri) => {
+                    if let Some(value) = parse_priority(&e, reader, context)? {
+                        priority.push(value)
+                    }
+                }
+                _ => warn_unexpected_text(&e, reader, context, "k_ele")?,
+            },
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    check_key(&kanji, "Kanji", reader, context)?;
+
+    Ok(KanjiEntry {
+        kanji,
+        info,
+        priority,
+    })
+}
+
+fn parse_r_ele<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<ReadingEntry, Error> {
+    let mut kana = String::new();
+    let mut no_kanji = false;
+    let mut related_kanji: Vec<String> = Vec::new();
+    let mut info: InfoVec<ReadingInfo> = InfoVec::new();
+    let mut priority: PriorityVec = PriorityVec::new();
+
+    enum Elem {
+        Reb,
+        ReRestr,
+        ReInf,
+        RePri,
+    }
+    let mut elem: Option<Elem> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"reb" => elem = Some(Elem::Reb),
+                b"re_nokanji" => no_kanji = true,
+                b"re_restr" => elem = Some(Elem::ReRestr),
+                b"re_inf" => elem = Some(Elem::ReInf),
+                b"re_pri" => elem = Some(Elem::RePri),
+                _ => warn_unknown_tag(e.name(), reader, context, "r_ele")?,
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"r_ele" => break,
+                _ => elem = None,
+            },
+            Ok(Event::Text(e)) => match elem {
+                Some(Elem::Reb) => kana = e.unescape_and_decode(reader)?,
+                Some(Elem::ReRestr) => related_kanji.push(e.unescape_and_decode(reader)?),
+                Some(Elem::ReInf) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        info.push(value)
+                    }
+                }
+                Some(Elem::RePri) => {
+                    if let Some(value) = parse_priority(&e, reader, context)? {
+                        priority.push(value)
+                    }
+                }
+                _ => warn_unexpected_text(&e, reader, context, "r_ele")?,
+            },
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    check_key(&kana, "Kana", reader, context)?;
+
+    Ok(ReadingEntry {
+        kana,
+        no_kanji,
+        related_kanji,
+        info,
+        priority,
+    })
+}
+
+fn check_key<T: BufRead>(
+    key: &str,
+    name: &str,
+    reader: &Reader<T>,
+    context: &mut EntryContext,
+) -> Result<(), Error> {
+    if key.is_empty() {
+        context.report(
+            reader,
+            DiagnosticKind::EmptyKey,
+            format!("{} key is empty", name),
+        )
+    } else if key.trim() != key {
+        context.report(
+            reader,
+            DiagnosticKind::KeyWhitespace,
+            format!("{} key \"{}\" has leading or trailing whitespace", name, key),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_priority<T: BufRead>(
+    text: &BytesText,
+    reader: &Reader<T>,
+    context: &mut EntryContext,
+) -> Result<Option<Priority>, Error> {
+    let code = text.unescape_and_decode(reader)?;
+    context.check_value(reader, Priority::from_str(&code))
+}
+
+fn parse_sense<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<Sense, Error> {
+    let mut only_kanji: Vec<String> = Vec::new();
+    let mut only_readings: Vec<String> = Vec::new();
+    let mut part_of_speech: Vec<PartOfSpeech> = Vec::new();
+    let mut cross_refs: Vec<CrossReference> = Vec::new();
+    let mut antonyms: Vec<CrossReference> = Vec::new();
+    let mut field: Vec<Field> = Vec::new();
+    let mut misc: Vec<Misc> = Vec::new();
+    let mut sense_info: Vec<String> = Vec::new();
+    let mut lang_sources: Vec<LangSource> = Vec::new();
+    let mut dialect: Vec<Dialect> = Vec::new();
+    let mut glosses: Vec<Gloss> = Vec::new();
+    let mut lang: Option<String> = None;
+
+    enum Elem {
+        SenseTagKanji,
+        SenseTagReading,
+        PartOfSpeech,
+        CrossReference,
+        Antonym,
+        Field,
+        Misc,
+        SenseInfo,
+        LangSource,
+        Dialect,
+        Gloss(Option<GlossType>, Option<String>),
+    }
+    let mut elem: Option<Elem> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"stagk" => elem = Some(Elem::SenseTagKanji),
+                b"stagr" => elem = Some(Elem::SenseTagReading),
+                b"pos" => elem = Some(Elem::PartOfSpeech),
+                b"xref" => elem = Some(Elem::CrossReference),
+                b"ant" => elem = Some(Elem::Antonym),
+                b"field" => elem = Some(Elem::Field),
+                b"misc" => elem = Some(Elem::Misc),
+                b"s_inf" => elem = Some(Elem::SenseInfo),
+                b"lsource" => {
+                    elem = Some(Elem::LangSource);
+                    lang_sources.push(parse_lsource_attributes(e, reader, context)?);
+                }
+                b"dial" => elem = Some(Elem::Dialect),
+                b"gloss" => {
+                    let mut gloss_type: Option<GlossType> = None;
+                    let mut gender: Option<String> = None;
+                    for attr in e.attributes().flatten() {
+                        match attr.key {
+                            b"xml:lang" => {
+                                let lang_str = attr.unescape_and_decode_value(reader)?;
+                                match lang {
+                                    Some(ref current_lang_str) if *current_lang_str != lang_str => {
+                                        context.report(
+                                            reader,
+                                            DiagnosticKind::MixedGlossLanguages,
+                                            format!(
+                                                "Gloss language \"{}\" differs from the \"{}\" \
+                                                 of the other glosses in the sense",
+                                                lang_str, current_lang_str
+                                            ),
+                                        )?;
+                                    }
+                                    Some(_) => (),
+                                    None => lang = Some(lang_str),
+                                };
+                            }
+                            b"g_type" => {
+                                let value = attr.unescape_and_decode_value(reader)?;
+                                let value = GlossType::from_str(&value);
+                                gloss_type = context.check_value(reader, value)?;
+                            }
+                            b"g_gend" => gender = Some(attr.unescape_and_decode_value(reader)?),
+                            _ => (),
+                        }
+                    }
+                    elem = Some(Elem::Gloss(gloss_type, gender));
+                }
+                _ => warn_unknown_tag(e.name(), reader, context, "sense")?,
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"sense" => break,
+                _ => elem = None,
+            },
+            Ok(Event::Text(e)) => match elem {
+                Some(Elem::SenseTagKanji) => {
+                    only_kanji.push(e.unescape_and_decode(reader)?)
+                }
+                Some(Elem::SenseTagReading) => {
+                    only_readings.push(e.unescape_and_decode(reader)?)
+                }
+                Some(Elem::PartOfSpeech) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        part_of_speech.push(value)
+                    }
+                }
+                Some(Elem::CrossReference) => {
+                    let xref = parse_cross_ref(&e.unescape_and_decode(reader)?);
+                    if let Some(value) = context.check_value(reader, xref)? {
+                        cross_refs.push(value)
+                    }
+                }
+                Some(Elem::Antonym) => {
+                    let xref = parse_cross_ref(&e.unescape_and_decode(reader)?);
+                    if let Some(value) = context.check_value(reader, xref)? {
+                        antonyms.push(value)
+                    }
+                }
+                Some(Elem::Field) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        field.push(value)
+                    }
+                }
+                Some(Elem::Misc) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        misc.push(value)
+                    }
+                }
+                Some(Elem::SenseInfo) => sense_info.push(e.unescape_and_decode(reader)?),
+                Some(Elem::LangSource) => {
+                    // We push the LangSource when we encounter the start tag since the text
+                    // content is optional.
+                    if let Some(lang_source) = lang_sources.last_mut() {
+                        lang_source.original = Some(e.unescape_and_decode(reader)?);
+                    }
+                }
+                Some(Elem::Dialect) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        dialect.push(value)
+                    }
+                }
+                Some(Elem::Gloss(gloss_type, ref gender)) => glosses.push(Gloss {
+                    text: e.unescape_and_decode(reader)?,
+                    gloss_type,
+                    gender: gender.clone(),
+                }),
+                _ => warn_unexpected_text(&e, reader, context, "sense")?,
+            },
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    Ok(Sense {
+        only_kanji,
+        only_readings,
+        part_of_speech,
+        cross_refs,
+        antonyms,
+        field,
+        misc,
+        sense_info,
+        lang_sources,
+        dialect,
+        glosses,
+        lang,
+    })
+}
+
+fn parse_trans<T: BufRead>(
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<Translation, Error> {
+    let mut name_type: Vec<NameType> = Vec::new();
+    let mut cross_refs: Vec<CrossReference> = Vec::new();
+    let mut details: Vec<String> = Vec::new();
+    let mut lang: Option<String> = None;
+
+    enum Elem {
+        NameType,
+        CrossReference,
+        Detail,
+    }
+    let mut elem: Option<Elem> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name() {
+                b"name_type" => elem = Some(Elem::NameType),
+                b"xref" => elem = Some(Elem::CrossReference),
+                b"trans_det" => {
+                    elem = Some(Elem::Detail);
+                    for attr in e.attributes().flatten() {
+                        if attr.key == b"xml:lang" {
+                            let lang_str = attr.unescape_and_decode_value(reader)?;
+                            match lang {
+                                Some(ref current_lang_str) if *current_lang_str != lang_str => {
+                                    context.report(
+                                        reader,
+                                        DiagnosticKind::MixedGlossLanguages,
+                                        format!(
+                                            "Translation language \"{}\" differs from the \"{}\" \
+                                             of the other translations in the trans element",
+                                            lang_str, current_lang_str
+                                        ),
+                                    )?;
+                                }
+                                Some(_) => (),
+                                None => lang = Some(lang_str),
+                            };
+                        }
+                    }
+                }
+                _ => warn_unknown_tag(e.name(), reader, context, "trans")?,
+            },
+            Ok(Event::End(ref e)) => match e.name() {
+                b"trans" => break,
+                _ => elem = None,
+            },
+            Ok(Event::Text(e)) => match elem {
+                Some(Elem::NameType) => {
+                    if let Some(value) = parse_entity(&e, reader, context)? {
+                        name_type.push(value)
+                    }
+                }
+                Some(Elem::CrossReference) => {
+                    let xref = parse_cross_ref(&e.unescape_and_decode(reader)?);
+                    if let Some(value) = context.check_value(reader, xref)? {
+                        cross_refs.push(value)
+                    }
+                }
+                Some(Elem::Detail) => details.push(e.unescape_and_decode(reader)?),
+                _ => warn_unexpected_text(&e, reader, context, "trans")?,
+            },
+            Err(e) => bail!("Error parsing XML: {}", e),
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    Ok(Translation {
+        name_type,
+        cross_refs,
+        details,
+        lang,
+    })
+}
+
+fn parse_lsource_attributes<T: BufRead>(
+    start: &BytesStart,
+    reader: &Reader<T>,
+    context: &mut EntryContext,
+) -> Result<LangSource, Error> {
+    let mut lang_source = LangSource {
+        lang: "eng".to_owned(),
+        original: None,
+        partial: false,
+        wasei: false,
+    };
+
+    for attr in start.attributes() {
+        let attr = attr?;
+        let value = attr.unescape_and_decode_value(reader)?;
+        match attr.key {
+            b"xml:lang" => lang_source.lang = value,
+            b"ls_type" if value == "part" => lang_source.partial = true,
+            b"ls_wasei" if value == "y" => lang_source.wasei = true,
+            b"ls_type" | b"ls_wasei" => context.report(
+                reader,
+                DiagnosticKind::InvalidValue,
+                format!(
+                    "Unexpected {} value \"{}\"",
+                    reader.decode(attr.key),
+                    value
+                ),
+            )?,
+            _ => (),
+        }
+    }
+
+    Ok(lang_source)
+}
+
+#[test]
+fn test_parse_sense() {
+    let xml = r#"<sense>
+                 <stagk>延べる</stagk>
+                 <stagk>伸べる</stagk>
+                 <gloss>to postpone</gloss>
+                 <gloss>to extend</gloss>
+                 </sense>"#;
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let _ = reader.read_event(&mut buf);
+    let positions = Positions::default();
+    let mut sink = Vec::new();
+    let mut context = EntryContext::new(&positions, &mut sink);
+    assert_eq!(
+        parse_sense(&mut reader, &mut context).unwrap(),
+        Sense {
+            only_kanji: vec!["延べる".to_owned(), "伸べる".to_owned()],
+            only_readings: vec![],
+            antonyms: vec![],
+            part_of_speech: vec![],
+            cross_refs: vec![],
+            field: vec![],
+            misc: vec![],
+            sense_info: vec![],
+            lang_sources: vec![],
+            dialect: vec![],
+            glosses: vec![Gloss::new("to postpone"), Gloss::new("to extend")],
+            lang: None,
+        }
+    );
+}
+
+#[test]
+fn test_parse_sense_info_lsource_and_dial() {
+    let xml = r#"<sense>
+                 <misc>&uk;</misc>
+                 <s_inf>usu. as a prefix</s_inf>
+                 <lsource xml:lang="ger">Arbeit</lsource>
+                 <lsource xml:lang="fre" ls_type="part">pomme</lsource>
+                 <lsource ls_wasei="y"/>
+                 <dial>&ksb;</dial>
+                 <gloss>part-time job</gloss>
+                 </sense>"#;
+    let mut reader = Reader::from_str(xml);
+    reader.expand_empty_elements(true);
+    let mut buf = Vec::new();
+    let _ = reader.read_event(&mut buf);
+    let positions = Positions::default();
+    let mut sink = Vec::new();
+    let sense = parse_sense(&mut reader, &mut EntryContext::new(&positions, &mut sink)).unwrap();
+    assert_eq!(sense.misc, vec![Misc::Uk]);
+    assert_eq!(sense.sense_info, vec!["usu. as a prefix".to_owned()]);
+    assert_eq!(
+        sense.lang_sources,
+        vec![
+            LangSource {
+                lang: "ger".to_owned(),
+                original: Some("Arbeit".to_owned()),
+                partial: false,
+                wasei: false,
+            },
+            LangSource {
+                lang: "fre".to_owned(),
+                original: Some("pomme".to_owned()),
+                partial: true,
+                wasei: false,
+            },
+            LangSource {
+                lang: "eng".to_owned(),
+                original: None,
+                partial: false,
+                wasei: true,
+            },
+        ]
+    );
+    assert_eq!(sense.dialect, vec![Dialect::Ksb]);
+    assert_eq!(sense.glosses, vec![Gloss::new("part-time job")]);
+}
+
+#[test]
+fn test_parse_gloss_attributes() {
+    let xml = r#"<sense>
+                 <gloss xml:lang="ger" g_gend="fem">Teilzeitarbeit</gloss>
+                 <gloss xml:lang="ger" g_type="expl">Job</gloss>
+                 </sense>"#;
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let _ = reader.read_event(&mut buf);
+    let positions = Positions::default();
+    let mut sink = Vec::new();
+    let sense = parse_sense(&mut reader, &mut EntryContext::new(&positions, &mut sink)).unwrap();
+    assert_eq!(sense.lang, Some("ger".to_owned()));
+    assert_eq!(
+        sense.glosses,
+        vec![
+            Gloss {
+                text: "Teilzeitarbeit".to_owned(),
+                gloss_type: None,
+                gender: Some("fem".to_owned()),
+            },
+            Gloss {
+                text: "Job".to_owned(),
+                gloss_type: Some(GlossType::Expl),
+                gender: None,
+            },
+        ]
+    );
+
+    let mut reader = Reader::from_str(r#"<sense><gloss g_type="pun">x</gloss></sense>"#);
+    let _ = reader.read_event(&mut buf);
+    assert_eq!(
+        parse_sense(&mut reader, &mut EntryContext::new(&positions, &mut sink))
+            .unwrap_err()
+            .to_string(),
+        "Unexpected g_type value \"pun\""
+    );
+}
+
+/// Parse the text of an element whose content is an entity, e.g. pos or misc.
+///
+/// Normally the text is an entity reference like "&adj-i;" but if the document's entities have
+/// been expanded we look up the code for the expanded text instead.
+fn parse_entity<E: Entity, T: BufRead>(
+    text: &BytesText,
+    reader: &mut Reader<T>,
+    context: &mut EntryContext,
+) -> Result<Option<E>, Error> {
+    let expansions = match context.expansions {
+        Some(expansions) => expansions,
+        None => {
+            let entity = parse_single_entity(text.escaped(), reader);
+            return context.check_value(reader, entity);
+        }
+    };
+
+    let expansion = text.unescape_and_decode(reader)?;
+    let codes = expansions.get(&expansion).map_or(&[][..], Vec::as_slice);
+    let entity = codes
+        .iter()
+        .find_map(|code| E::from_code(code))
+        .ok_or_else(|| format_err!("Unrecognized entity expansion \"{}\"", expansion));
+    context.check_value(reader, entity)
+}
+
+#[test]
+fn test_expanded_entities() {
+    let raw: Vec<Entry> = EntryReader::from_file("data/sample.xml")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let mut reader = EntryReader::from_file("data/sample-noent.xml").unwrap();
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Error parsing entity unclassified in entry 1000000 at line 375, column 19"
+    );
+
+    let mut reader = EntryReader::from_file("data/sample-noent.xml").unwrap();
+    reader.expanded_entities();
+    let expanded: Vec<Entry> = reader.collect::<Result<_, _>>().unwrap();
+    assert_eq!(expanded.len(), 11);
+    // The expanded sample omits some of the entries
+    for entry in &expanded {
+        assert_eq!(Some(entry), raw.iter().find(|raw| raw.id == entry.id));
+    }
+
+    let xml = r#"<!DOCTYPE JMdict [<!ENTITY n "noun (common) (futsuumeishi)">]>
+<JMdict><entry><ent_seq>1</ent_seq><r_ele><reb>かな</reb></r_ele>
+<sense><pos>noun</pos></sense></entry></JMdict>"#;
+    let mut reader = EntryReader::new(xml.as_bytes());
+    reader.expanded_entities();
+    assert_eq!(
+        reader.next().unwrap().unwrap_err().to_string(),
+        "Unrecognized entity expansion \"noun\" in entry 1 at line 3, column 18"
+    );
+}
+
+/// Take a string like "&ent;" and return the corresponding entity value.
+///
+/// The entity types are generated from simple lists of codes by build.rs (see entities.rs) so we
+/// never need to store the decoded strings and any entity we don't recognize is an error.
+fn parse_single_entity<E: Entity, T: BufRead>(
+    raw: &[u8],
+    reader: &mut Reader<T>,
+) -> Result<E, Error> {
+    // Check we start with &, end with ;, and have nothing inbetween.
+    if !raw.starts_with(b"&") || !raw.ends_with(b";") || memchr::memchr(b'&', &raw[1..]).is_some()
+        || memchr::memchr(b';', &raw[..raw.len() - 1]).is_some()
+    {
+        bail!("Error parsing entity {}", reader.decode(raw))
+    }
+
+    let code = reader.decode(&raw[1..raw.len() - 1]);
+    match E::from_code(&code) {
+        Some(entity) => Ok(entity),
+        None => bail!("Unrecognized entity &{};", code),
+    }
+}
+
+#[test]
+fn test_parse_single_entity() {
+    let mut reader = Reader::from_str("");
+    assert_eq!(
+        parse_single_entity::<PartOfSpeech, _>(b"&adj-na;", &mut reader).unwrap(),
+        PartOfSpeech::AdjNa
+    );
+    assert!(parse_single_entity::<PartOfSpeech, _>(b"&uk;", &mut reader).is_err());
+    assert!(parse_single_entity::<Misc, _>(b"uk", &mut reader).is_err());
+    assert!(parse_single_entity::<Misc, _>(b"&uk;&uk;", &mut reader).is_err());
+}
+
+pub fn parse_cross_ref(input: &str) -> Result<CrossReference, Error> {
+    ensure!(!input.is_empty(), "Empty cross-reference");
+
+    let parts: Vec<&str> = input.split('・').collect();
+
+    // Simple case, no separators
+    if parts.len() == 1 {
+        return Ok(CrossReference {
+            kanji_or_reading: input.to_owned(),
+            reading: None,
+            sense_index: None,
+            target: None,
+        });
+    }
+
+    // The middle dot can either be the separator of the kanji / reading / sense OR it can just be
+    // the regular separator in a katakana word.
+
+    // If the last part is an integer, assign the sense.
+    let sense_index: Option<u8> = parts.last().unwrap().parse::<u8>().ok();
+    let non_sense_parts = if sense_index.is_some() {
+        parts.len() - 1
+    } else {
+        parts.len()
+    };
+
+    // Assign the other parts depending on if we're likely looking at a katakana word or a regular
+    // entry.
+    let mut reading: Option<String> = None;
+    let kanji_or_reading = if is_katakana(parts.first().unwrap()) {
+        parts[0..non_sense_parts].join("・").to_owned()
+    } else {
+        ensure!(
+            non_sense_parts <= 2,
+            "Error parsing cross-reference {}",
+            input
+        );
+        // Assign the reading if we have one
+        if non_sense_parts == 2 {
+            reading = Some(parts[1].to_owned());
+        }
+        (*parts.first().unwrap()).to_owned()
+    };
+
+    Ok(CrossReference {
+        kanji_or_reading,
+        reading,
+        sense_index,
+        target: None,
+    })
+}
+
+#[test]
+fn test_parse_cross_ref() {
+    assert_eq!(
+        parse_cross_ref("集束").unwrap(),
+        CrossReference {
+            kanji_or_reading: "集束".to_owned(),
+            reading: None,
+            sense_index: None,
+            target: None,
+        }
+    );
+    assert_eq!(
+        parse_cross_ref("因・2").unwrap(),
+        CrossReference {
+            kanji_or_reading: "因".to_owned(),
+            reading: None,
+            sense_index: Some(2),
+            target: None,
+        }
+    );
+    assert_eq!(
+        parse_cross_ref("如何・どう").unwrap(),
+        CrossReference {
+            kanji_or_reading: "如何".to_owned(),
+            reading: Some("どう".to_owned()),
+            sense_index: None,
+            target: None,
+        }
+    );
+    assert_eq!(
+        parse_cross_ref("何方・どちら・1").unwrap(),
+        CrossReference {
+            kanji_or_reading: "何方".to_owned(),
+            reading: Some("どちら".to_owned()),
+            sense_index: Some(1),
+            target: None,
+        }
+    );
+    assert_eq!(
+        parse_cross_ref("ブロードノーズ・セブンギル・シャーク").unwrap(),
+        CrossReference {
+            kanji_or_reading: "ブロードノーズ・セブンギル・シャーク".to_owned(),
+            reading: None,
+            sense_index: None,
+            target: None,
+        }
+    );
+    // I'm not sure if this actually exists, but it seems possible.
+    assert_eq!(
+        parse_cross_ref("カタカナ・コトバ・2").unwrap(),
+        CrossReference {
+            kanji_or_reading: "カタカナ・コトバ".to_owned(),
+            reading: None,
+            sense_index: Some(2),
+            target: None,
+        }
+    );
+}
+
+fn warn_unknown_tag<T: BufRead>(
+    elem_name: &[u8],
+    reader: &Reader<T>,
+    context: &mut EntryContext,
+    ancestor: &str,
+) -> Result<(), Error> {
+    let message = match str::from_utf8(elem_name) {
+        Ok(tag) => format!("Unrecognized {} member element {}", ancestor, tag),
+        _ => format!("Unrecognized {} member element (non-utf8)", ancestor),
+    };
+    context.report(reader, DiagnosticKind::UnknownElement, message)
+}
+
+fn warn_unexpected_text<T: BufRead>(
+    text: &BytesText,
+    reader: &Reader<T>,
+    context: &mut EntryContext,
+    ancestor: &str,
+) -> Result<(), Error> {
+    let message = match text.unescape_and_decode(reader) {
+        Ok(text) => format!("Unexpected text \"{}\" in {} element", text, ancestor),
+        _ => format!("Unexpected text in {} element (non-utf8)", ancestor),
+    };
+    context.report(reader, DiagnosticKind::UnexpectedText, message)
+}
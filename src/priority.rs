@@ -0,0 +1,144 @@
+use failure::Error;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A ke_pri or re_pri value indicating that a word appears in a particular frequency list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// news1: In the first 12,000 words of the Mainichi Shimbun "wordfreq" file
+    News1,
+    /// news2: In the second 12,000 words of the Mainichi Shimbun "wordfreq" file
+    News2,
+    /// ichi1: In the "Ichimango goi bunruishuu"
+    Ichi1,
+    /// ichi2: Demoted from ichi1 due to low frequency in the WWW and newspapers
+    Ichi2,
+    /// spec1: A small number of words detected as being common but not in the other lists
+    Spec1,
+    /// spec2: As with spec1 but less common
+    Spec2,
+    /// gai1: Common loanwords, based on the wordfreq file
+    Gai1,
+    /// gai2: As with gai1 but less common
+    Gai2,
+    /// nfXX: The frequency band (1-48) in the wordfreq file where each band is 500 words
+    Nf(u8),
+}
+
+impl Priority {
+    /// True if this is one of the priorities used to mark an entry as "common", i.e. the (P)
+    /// marker in EDICT.
+    pub fn is_common(&self) -> bool {
+        matches!(
+            *self,
+            Priority::News1 | Priority::Ichi1 | Priority::Spec1 | Priority::Spec2 | Priority::Gai1
+        )
+    }
+
+    /// A rough measure of how common a word with this priority is where higher is more common.
+    ///
+    /// The nfXX bands give the finest-grained ranking so they map to 1 (nf48) to 48 (nf01). The
+    /// other lists are coarser so we rank their first tier alongside the most common nf bands and
+    /// their second tier alongside the less common ones.
+    pub fn score(&self) -> u32 {
+        match *self {
+            Priority::Nf(band) => 49 - u32::from(band),
+            _ if self.is_common() => 40,
+            _ => 20,
+        }
+    }
+}
+
+/// Combine the scores of a set of priorities. Words that appear in more lists get a higher score.
+pub fn priority_score(priorities: &[Priority]) -> u32 {
+    priorities.iter().map(Priority::score).sum()
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(code: &str) -> Result<Priority, Error> {
+        Ok(match code {
+            "news1" => Priority::News1,
+            "news2" => Priority::News2,
+            "ichi1" => Priority::Ichi1,
+            "ichi2" => Priority::Ichi2,
+            "spec1" => Priority::Spec1,
+            "spec2" => Priority::Spec2,
+            "gai1" => Priority::Gai1,
+            "gai2" => Priority::Gai2,
+            _ if code.starts_with("nf") => {
+                // The band is always two digits whereas u8::from_str also accepts a sign
+                let digits = &code[2..];
+                ensure!(
+                    digits.len() == 2 && digits.bytes().all(|b| b.is_ascii_digit()),
+                    "Invalid frequency band \"{}\"",
+                    code
+                );
+                match u8::from_str(digits) {
+                    Ok(band) if (1..=48).contains(&band) => Priority::Nf(band),
+                    _ => bail!("Invalid frequency band \"{}\"", code),
+                }
+            }
+            _ => bail!("Unrecognized priority code \"{}\"", code),
+        })
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Priority::News1 => f.write_str("news1"),
+            Priority::News2 => f.write_str("news2"),
+            Priority::Ichi1 => f.write_str("ichi1"),
+            Priority::Ichi2 => f.write_str("ichi2"),
+            Priority::Spec1 => f.write_str("spec1"),
+            Priority::Spec2 => f.write_str("spec2"),
+            Priority::Gai1 => f.write_str("gai1"),
+            Priority::Gai2 => f.write_str("gai2"),
+            Priority::Nf(band) => write!(f, "nf{:02}", band),
+        }
+    }
+}
+
+/// Priorities are serialized using their original codes, e.g. "nf17".
+impl Serialize for Priority {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[test]
+fn test_parse_priority() {
+    assert_eq!(Priority::from_str("news1").unwrap(), Priority::News1);
+    assert_eq!(Priority::from_str("gai2").unwrap(), Priority::Gai2);
+    assert_eq!(Priority::from_str("nf01").unwrap(), Priority::Nf(1));
+    assert_eq!(Priority::from_str("nf48").unwrap(), Priority::Nf(48));
+    assert_eq!(Priority::Nf(7).to_string(), "nf07");
+    assert!(Priority::from_str("nf49").is_err());
+    assert!(Priority::from_str("nf00").is_err());
+    assert!(Priority::from_str("nf1").is_err());
+    assert!(Priority::from_str("nf+1").is_err());
+    assert!(Priority::from_str("nf-1").is_err());
+    assert!(Priority::from_str("nf 1").is_err());
+    assert!(Priority::from_str("nf001").is_err());
+    assert!(Priority::from_str("nf").is_err());
+    assert_eq!(
+        Priority::from_str("nf+1").unwrap_err().to_string(),
+        "Invalid frequency band \"nf+1\""
+    );
+    assert!(Priority::from_str("news3").is_err());
+    assert!(Priority::from_str("").is_err());
+}
+
+#[test]
+fn test_priority_score() {
+    assert_eq!(priority_score(&[]), 0);
+    assert!(
+        priority_score(&[Priority::News1, Priority::Nf(1)])
+            > priority_score(&[Priority::News1, Priority::Nf(20)])
+    );
+    assert!(priority_score(&[Priority::Ichi1]) > priority_score(&[Priority::Ichi2]));
+    assert!(priority_score(&[Priority::Spec2]) > priority_score(&[Priority::News2]));
+}
@@ -0,0 +1,61 @@
+//! Information identifying the release of JMdict (or JMnedict) that a document came from.
+
+/// Describes the release that was read by an `EntryReader` or `NameReader`.
+///
+/// See `EntryReader::release_info`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseInfo {
+    /// The date from the "JMdict created: 2018-02-14" comment that precedes the entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// The revision numbers from the "Rev 1.08" comments describing the DTD, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dtd_revisions: Vec<String>,
+    /// The SHA-256 hash of the (decompressed) input as hex
+    pub input_hash: String,
+    /// The number of entries read
+    pub entry_count: usize,
+}
+
+impl ReleaseInfo {
+    /// Record the creation date or DTD revision from a comment preceding the root element, if it
+    /// contains either.
+    pub fn add_comment(&mut self, comment: &str) {
+        let comment = comment.trim();
+        if let Some(rest) = comment.strip_prefix("Rev ") {
+            if let Some(revision) = rest.split_whitespace().next() {
+                self.dtd_revisions.push(revision.to_owned());
+            }
+        } else if let Some(start) = comment.find(" created: ") {
+            let date = comment[start + " created: ".len()..].trim();
+            self.created = Some(date.to_owned());
+        }
+    }
+
+    /// Whether this release was created before `other`. Releases without a creation date are
+    /// never considered older.
+    pub fn is_older_than(&self, other: &ReleaseInfo) -> bool {
+        match (&self.created, &other.created) {
+            // The dates are ISO 8601 so they can be compared as strings
+            (Some(created), Some(other_created)) => created < other_created,
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn test_add_comment() {
+    let mut release = ReleaseInfo::default();
+    release.add_comment(" Rev 1.08 \n\tDelete <info> and <example> elements\n");
+    release.add_comment(" Rev 1.07 \n\tRevised POS tags for the adjectives\n");
+    release.add_comment(" Some other comment ");
+    release.add_comment(" JMdict created: 2018-02-14 ");
+    assert_eq!(release.created, Some("2018-02-14".to_owned()));
+    assert_eq!(release.dtd_revisions, ["1.08", "1.07"]);
+
+    let mut newer = release.clone();
+    newer.created = Some("2018-03-01".to_owned());
+    assert!(release.is_older_than(&newer));
+    assert!(!newer.is_older_than(&release));
+    assert!(!ReleaseInfo::default().is_older_than(&newer));
+}
@@ -0,0 +1,182 @@
+use failure::{Error, ResultExt};
+use jmdict::diagnostic::{Diagnostic, DiagnosticSink, Severity};
+use serde_json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Where the diagnostics reported while reading the input go.
+enum Output {
+    Stderr,
+    /// One JSON diagnostic per line
+    File(BufWriter<File>),
+    /// Kept for the --check report
+    Collect(Vec<Diagnostic>),
+}
+
+/// Receives the diagnostics from the parser, counting them as they are reported.
+pub struct Reporter {
+    output: Output,
+    pub warnings: usize,
+    pub errors: usize,
+    /// Fail as soon as a diagnostic is reported, after writing it (--strict)
+    pub strict: bool,
+}
+
+impl Reporter {
+    pub fn stderr() -> Reporter {
+        Reporter::new(Output::Stderr)
+    }
+
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<Reporter, Error> {
+        let file = File::create(path).context("Could not create diagnostics file")?;
+        Ok(Reporter::new(Output::File(BufWriter::new(file))))
+    }
+
+    pub fn collect() -> Reporter {
+        Reporter::new(Output::Collect(Vec::new()))
+    }
+
+    fn new(output: Output) -> Reporter {
+        Reporter {
+            output,
+            warnings: 0,
+            errors: 0,
+            strict: false,
+        }
+    }
+
+    /// The diagnostics reported so far if they are being collected.
+    pub fn collected(&self) -> &[Diagnostic] {
+        match self.output {
+            Output::Collect(ref diagnostics) => diagnostics,
+            _ => &[],
+        }
+    }
+
+    /// Flush any diagnostics that have yet to be written.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if let Output::File(ref mut file) = self.output {
+            file.flush().context("Failed to write diagnostics")?;
+        }
+        Ok(())
+    }
+
+    /// A count of the diagnostics reported, e.g. "2 warnings, 1 error".
+    pub fn summary(&self) -> String {
+        format!(
+            "{}, {}",
+            plural(self.warnings, "warning"),
+            plural(self.errors, "error")
+        )
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
+
+impl DiagnosticSink for Reporter {
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), Error> {
+        let severity = diagnostic.severity;
+        match severity {
+            Severity::Warning => self.warnings += 1,
+            Severity::Error => self.errors += 1,
+        }
+
+        match self.output {
+            Output::Stderr => match diagnostic.severity {
+                Severity::Warning => eprintln!("WARNING: {}", diagnostic),
+                Severity::Error => eprintln!("ERROR: {}", diagnostic),
+            },
+            Output::File(ref mut file) => {
+                serde_json::to_writer(&mut *file, &diagnostic)
+                    .context("Failed to write diagnostics")?;
+                file.write_all(b"\n").context("Failed to write diagnostics")?;
+            }
+            Output::Collect(ref mut diagnostics) => diagnostics.push(diagnostic),
+        }
+
+        let noun = match severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        ensure!(!self.strict, "Stopping at the first {} (--strict)", noun);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reporter() {
+    use jmdict::diagnostic::DiagnosticKind;
+    use jmdict::input::Position;
+    use serde_json::Value;
+    use std::fs;
+
+    let diagnostic = |kind| {
+        Diagnostic::new(
+            kind,
+            "Message".to_owned(),
+            Some(1),
+            Position { line: 2, column: 3 },
+        )
+    };
+
+    let mut reporter = Reporter::collect();
+    reporter.report(diagnostic(DiagnosticKind::UnknownElement)).unwrap();
+    reporter.report(diagnostic(DiagnosticKind::EmptyKey)).unwrap();
+    assert_eq!(reporter.collected().len(), 2);
+    assert_eq!(reporter.summary(), "1 warning, 1 error");
+
+    let path = ::std::env::temp_dir().join(format!("jmdict-diagnostics-{}", ::std::process::id()));
+    let mut reporter = Reporter::file(&path).unwrap();
+    reporter.report(diagnostic(DiagnosticKind::UnexpectedText)).unwrap();
+    reporter.report(diagnostic(DiagnosticKind::UnknownElement)).unwrap();
+    reporter.finish().unwrap();
+    assert!(reporter.collected().is_empty());
+    assert_eq!(reporter.summary(), "2 warnings, 0 errors");
+
+    let written = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let lines: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["kind"], "unexpected_text");
+    assert_eq!(lines[0]["line"], 2);
+
+    let mut reporter = Reporter::collect();
+    reporter.strict = true;
+    let error = reporter.report(diagnostic(DiagnosticKind::UnknownElement)).unwrap_err();
+    assert_eq!(error.to_string(), "Stopping at the first warning (--strict)");
+    assert_eq!(reporter.collected().len(), 1);
+}
+
+#[test]
+fn test_strict_kanjidic() {
+    use jmdict::kanjidic::CharacterReader;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let xml = "<kanjidic2><character><literal>亜</literal><misc><stroke_count>7</stroke_count>\
+               <variant var_type=\"jis208\">1-48-19</variant></misc><unknown>x</unknown>\
+               </character></kanjidic2>";
+    let mut reporter = Reporter::collect();
+    reporter.strict = true;
+    let reporter = Rc::new(RefCell::new(reporter));
+    let mut reader = CharacterReader::new(xml.as_bytes());
+    reader.diagnostic_sink(reporter.clone());
+
+    // The variant is skipped silently but the unknown element stops the reader
+    let error = reader.next().unwrap().unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Stopping at the first warning (--strict) in character 亜"));
+    assert!(reader.next().is_none());
+    assert_eq!(reporter.borrow().collected().len(), 1);
+    assert_eq!(reporter.borrow().warnings, 1);
+}
@@ -0,0 +1,372 @@
+//! Export of entries to a single-file SQLite database.
+//!
+//! Each level of the entry tree gets its own table (`entries`, `kanji`, `readings`, `senses`,
+//! `glosses` and `xrefs`) whose rows refer to their parent by ID. The re_restr, stagk and stagr
+//! restrictions, which refer to kanji and readings by their text, are stored as links to the
+//! matching `kanji` and `readings` rows in `reading_restrictions` and `sense_restrictions`.
+//! Restrictions that don't match any kanji or reading of the entry are dropped (they are reported
+//! as warnings when parsing).
+//!
+//! Lists of entity codes and priorities, such as parts of speech, are stored as JSON arrays using
+//! the same representation as the CouchDB documents, or NULL when empty.
+//!
+//! `glosses_fts` and `readings_fts` are FTS5 indexes over the glosses and readings, e.g.
+//!
+//! ```sql
+//! SELECT entry_id FROM glosses JOIN senses ON senses.id = sense_id
+//!   WHERE glosses.id IN (SELECT rowid FROM glosses_fts WHERE glosses_fts MATCH 'player');
+//! ```
+
+use failure::{Error, ResultExt};
+use jmdict::{CrossReference, Entry};
+use rusqlite::{Connection, Transaction};
+use serde::Serialize;
+use serde_json;
+use std::fs;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE entries (
+        id INTEGER PRIMARY KEY,
+        priority_score INTEGER NOT NULL
+    );
+    CREATE TABLE kanji (
+        id INTEGER PRIMARY KEY,
+        entry_id INTEGER NOT NULL REFERENCES entries(id),
+        position INTEGER NOT NULL,
+        kanji TEXT NOT NULL,
+        info TEXT,
+        priority TEXT
+    );
+    CREATE TABLE readings (
+        id INTEGER PRIMARY KEY,
+        entry_id INTEGER NOT NULL REFERENCES entries(id),
+        position INTEGER NOT NULL,
+        kana TEXT NOT NULL,
+        no_kanji INTEGER NOT NULL,
+        info TEXT,
+        priority TEXT
+    );
+    CREATE TABLE reading_restrictions (
+        reading_id INTEGER NOT NULL REFERENCES readings(id),
+        kanji_id INTEGER NOT NULL REFERENCES kanji(id)
+    );
+    CREATE TABLE senses (
+        id INTEGER PRIMARY KEY,
+        entry_id INTEGER NOT NULL REFERENCES entries(id),
+        position INTEGER NOT NULL,
+        lang TEXT NOT NULL,
+        part_of_speech TEXT,
+        field TEXT,
+        misc TEXT,
+        dialect TEXT,
+        sense_info TEXT,
+        lang_sources TEXT
+    );
+    CREATE TABLE sense_restrictions (
+        sense_id INTEGER NOT NULL REFERENCES senses(id),
+        kanji_id INTEGER REFERENCES kanji(id),
+        reading_id INTEGER REFERENCES readings(id)
+    );
+    CREATE TABLE glosses (
+        id INTEGER PRIMARY KEY,
+        sense_id INTEGER NOT NULL REFERENCES senses(id),
+        position INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        type TEXT,
+        gender TEXT
+    );
+    CREATE TABLE xrefs (
+        id INTEGER PRIMARY KEY,
+        sense_id INTEGER NOT NULL REFERENCES senses(id),
+        antonym INTEGER NOT NULL,
+        kanji_or_reading TEXT NOT NULL,
+        reading TEXT,
+        sense_index INTEGER,
+        target_id INTEGER REFERENCES entries(id)
+    );
+";
+
+/// Created once the tables are populated since that is faster than updating them as we go.
+const INDEXES: &str = "
+    CREATE INDEX kanji_entry_id ON kanji(entry_id);
+    CREATE INDEX kanji_kanji ON kanji(kanji);
+    CREATE INDEX readings_entry_id ON readings(entry_id);
+    CREATE INDEX readings_kana ON readings(kana);
+    CREATE INDEX reading_restrictions_reading_id ON reading_restrictions(reading_id);
+    CREATE INDEX senses_entry_id ON senses(entry_id);
+    CREATE INDEX sense_restrictions_sense_id ON sense_restrictions(sense_id);
+    CREATE INDEX glosses_sense_id ON glosses(sense_id);
+    CREATE INDEX xrefs_sense_id ON xrefs(sense_id);
+    CREATE INDEX xrefs_target_id ON xrefs(target_id);
+
+    CREATE VIRTUAL TABLE glosses_fts USING fts5(text, content='glosses', content_rowid='id');
+    INSERT INTO glosses_fts(glosses_fts) VALUES('rebuild');
+    CREATE VIRTUAL TABLE readings_fts USING fts5(kana, content='readings', content_rowid='id');
+    INSERT INTO readings_fts(readings_fts) VALUES('rebuild');
+";
+
+/// Write the entries to a new SQLite database at `path`, replacing any existing file.
+pub fn export_entries<P: AsRef<Path>>(entries: &[Entry], path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    if path.exists() {
+        fs::remove_file(path).context("Could not replace output file")?;
+    }
+    let mut conn = Connection::open(path).context("Could not create output file")?;
+    write_entries(&mut conn, entries)
+}
+
+/// Create the tables and write the entries to them in a single transaction.
+fn write_entries(conn: &mut Connection, entries: &[Entry]) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA).context("Failed to create tables")?;
+    for entry in entries {
+        write_entry(&tx, entry).with_context(|_| format!("Failed to write entry {}", entry.id))?;
+    }
+    tx.execute_batch(INDEXES).context("Failed to create indexes")?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn write_entry(tx: &Transaction, entry: &Entry) -> Result<(), Error> {
+    tx.prepare_cached("INSERT INTO entries (id, priority_score) VALUES (?1, ?2)")?
+        .execute((entry.id, entry.priority_score()))?;
+
+    let mut kanji_ids: Vec<(&str, i64)> = Vec::new();
+    for (position, k_ele) in entry.kanji_entries.iter().enumerate() {
+        tx.prepare_cached(
+            "INSERT INTO kanji (entry_id, position, kanji, info, priority)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?
+        .execute((
+            entry.id,
+            position,
+            &k_ele.kanji,
+            json_list(&k_ele.info)?,
+            json_list(&k_ele.priority)?,
+        ))?;
+        kanji_ids.push((&k_ele.kanji, tx.last_insert_rowid()));
+    }
+    let kanji_id = |kanji: &str| kanji_ids.iter().find(|k| k.0 == kanji).map(|k| k.1);
+
+    let mut reading_ids: Vec<(&str, i64)> = Vec::new();
+    for (position, r_ele) in entry.reading_entries.iter().enumerate() {
+        tx.prepare_cached(
+            "INSERT INTO readings (entry_id, position, kana, no_kanji, info, priority)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute((
+            entry.id,
+            position,
+            &r_ele.kana,
+            r_ele.no_kanji,
+            json_list(&r_ele.info)?,
+            json_list(&r_ele.priority)?,
+        ))?;
+        let reading_id = tx.last_insert_rowid();
+        reading_ids.push((&r_ele.kana, reading_id));
+
+        let related_kanji = r_ele.related_kanji.iter().filter_map(|kanji| kanji_id(kanji));
+        for kanji in related_kanji {
+            tx.prepare_cached(
+                "INSERT INTO reading_restrictions (reading_id, kanji_id) VALUES (?1, ?2)",
+            )?
+            .execute((reading_id, kanji))?;
+        }
+    }
+    let reading_id = |kana: &str| reading_ids.iter().find(|r| r.0 == kana).map(|r| r.1);
+
+    for (position, sense) in entry.senses.iter().enumerate() {
+        tx.prepare_cached(
+            "INSERT INTO senses (entry_id, position, lang, part_of_speech, field, misc, dialect,
+                                 sense_info, lang_sources)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?
+        .execute((
+            entry.id,
+            position,
+            sense.language(),
+            json_list(&sense.part_of_speech)?,
+            json_list(&sense.field)?,
+            json_list(&sense.misc)?,
+            json_list(&sense.dialect)?,
+            json_list(&sense.sense_info)?,
+            json_list(&sense.lang_sources)?,
+        ))?;
+        let sense_id = tx.last_insert_rowid();
+
+        let only_kanji = sense.only_kanji.iter().filter_map(|kanji| kanji_id(kanji));
+        for kanji in only_kanji {
+            tx.prepare_cached(
+                "INSERT INTO sense_restrictions (sense_id, kanji_id) VALUES (?1, ?2)",
+            )?
+            .execute((sense_id, kanji))?;
+        }
+        let only_readings = sense
+            .only_readings
+            .iter()
+            .filter_map(|kana| reading_id(kana));
+        for reading in only_readings {
+            tx.prepare_cached(
+                "INSERT INTO sense_restrictions (sense_id, reading_id) VALUES (?1, ?2)",
+            )?
+            .execute((sense_id, reading))?;
+        }
+
+        for (position, gloss) in sense.glosses.iter().enumerate() {
+            let gloss_type = match gloss.gloss_type {
+                Some(gloss_type) => serde_json::to_value(gloss_type)?
+                    .as_str()
+                    .map(str::to_owned),
+                None => None,
+            };
+            tx.prepare_cached(
+                "INSERT INTO glosses (sense_id, position, text, type, gender)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute((sense_id, position, &gloss.text, gloss_type, &gloss.gender))?;
+        }
+
+        for xref in &sense.cross_refs {
+            write_xref(tx, sense_id, xref, false)?;
+        }
+        for antonym in &sense.antonyms {
+            write_xref(tx, sense_id, antonym, true)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_xref(
+    tx: &Transaction,
+    sense_id: i64,
+    xref: &CrossReference,
+    antonym: bool,
+) -> Result<(), Error> {
+    tx.prepare_cached(
+        "INSERT INTO xrefs (sense_id, antonym, kanji_or_reading, reading, sense_index, target_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?
+    .execute((
+        sense_id,
+        antonym,
+        &xref.kanji_or_reading,
+        &xref.reading,
+        xref.sense_index,
+        xref.target,
+    ))?;
+    Ok(())
+}
+
+/// The values as a JSON array, or None if there are no values.
+fn json_list<T: Serialize>(values: &[T]) -> Result<Option<String>, Error> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        serde_json::to_string(values).context("Failed to serialize list")?,
+    ))
+}
+
+#[test]
+fn test_write_entries() {
+    use jmdict::EntryReader;
+
+    let mut entries = EntryReader::from_file("data/sample.xml")
+        .unwrap()
+        .collect::<Result<Vec<Entry>, Error>>()
+        .unwrap();
+    ::jmdict::resolve_cross_refs(&mut entries);
+    let mut conn = Connection::open_in_memory().unwrap();
+    write_entries(&mut conn, &entries).unwrap();
+
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("SELECT COUNT(*) FROM entries"), 13);
+    assert_eq!(
+        count("SELECT priority_score FROM entries WHERE id = 1000110"),
+        i64::from(entries[2].priority_score())
+    );
+
+    // re_restr
+    let kanji: String = conn
+        .query_row(
+            "SELECT kanji FROM kanji JOIN reading_restrictions ON kanji.id = kanji_id
+               JOIN readings ON readings.id = reading_id
+               WHERE kana = 'シーディープレイヤー'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(kanji, "ＣＤプレイヤー");
+    let priority: String = conn
+        .query_row(
+            "SELECT priority FROM kanji WHERE kanji = 'ＣＤプレーヤー'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(priority, "[\"spec1\"]");
+
+    // stagk and stagr
+    let mut statement = conn
+        .prepare(
+            "SELECT kanji FROM sense_restrictions JOIN kanji ON kanji.id = kanji_id
+               JOIN senses ON senses.id = sense_id
+               WHERE senses.entry_id = 1176390 AND senses.position = 1
+               ORDER BY kanji.position",
+        )
+        .unwrap();
+    let only_kanji = statement
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<Vec<String>, _>>()
+        .unwrap();
+    assert_eq!(only_kanji, ["延べる", "伸べる"]);
+    assert_eq!(
+        count(
+            "SELECT COUNT(*) FROM sense_restrictions JOIN readings ON readings.id = reading_id
+               WHERE kana = 'えん'"
+        ),
+        3
+    );
+
+    // Senses, glosses and cross-references
+    let part_of_speech: String = conn
+        .query_row(
+            "SELECT part_of_speech FROM senses WHERE entry_id = 1176390 AND position = 0",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(part_of_speech, "[\"v1\",\"vt\"]");
+    assert_eq!(
+        count("SELECT COUNT(*) FROM senses WHERE entry_id = 1000110 AND lang = 'ger'"),
+        1
+    );
+    assert_eq!(
+        count("SELECT COUNT(*) FROM xrefs WHERE antonym AND kanji_or_reading = '発散'"),
+        1
+    );
+    assert_eq!(
+        count("SELECT sense_index FROM xrefs WHERE kanji_or_reading = '何方'"),
+        1
+    );
+
+    // Full-text search
+    assert_eq!(
+        count(
+            "SELECT senses.entry_id FROM glosses JOIN senses ON senses.id = sense_id
+               WHERE glosses.id IN
+                 (SELECT rowid FROM glosses_fts WHERE glosses_fts MATCH 'postpone')"
+        ),
+        1_176_390
+    );
+    assert_eq!(
+        count(
+            "SELECT entry_id FROM readings
+               WHERE id IN (SELECT rowid FROM readings_fts WHERE readings_fts MATCH 'のべる')"
+        ),
+        1_176_390
+    );
+}
@@ -0,0 +1,249 @@
+//! A tiny in-memory stand-in for CouchDB that speaks just enough HTTP for the tests.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Default)]
+struct State {
+    /// Documents by database name and then by ID.
+    databases: BTreeMap<String, BTreeMap<String, Value>>,
+    /// The number of _bulk_docs requests received.
+    bulk_requests: usize,
+}
+
+pub struct TestServer {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let thread_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let _ = handle_connection(stream, &thread_state);
+            }
+        });
+
+        TestServer { url, state }
+    }
+
+    pub fn doc(&self, db: &str, id: &str) -> Option<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .databases
+            .get(db)
+            .and_then(|docs| docs.get(id))
+            .cloned()
+    }
+
+    pub fn doc_count(&self, db: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.databases.get(db).map_or(0, |docs| docs.len())
+    }
+
+    pub fn bulk_requests(&self) -> usize {
+        self.state.lock().unwrap().bulk_requests
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) -> ::std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_owned();
+    let target = parts.next().unwrap_or("").to_owned();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let mut header_parts = header.splitn(2, ':');
+        let name = header_parts.next().unwrap_or("").trim().to_lowercase();
+        if name == "content-length" {
+            content_length = usize::from_str(header_parts.next().unwrap_or("").trim()).unwrap_or(0);
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (status, response) = {
+        let mut state = state.lock().unwrap();
+        handle_request(&mut state, &method, &target, body)
+    };
+
+    let response = response.to_string();
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} Test\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    )?;
+    stream.flush()
+}
+
+#[allow(clippy::unnecessary_map_or)]
+fn handle_request(state: &mut State, method: &str, target: &str, body: Value) -> (u16, Value) {
+    let mut target_parts = target.splitn(2, '?');
+    let path = target_parts.next().unwrap_or("");
+    let query = target_parts.next().unwrap_or("");
+
+    let mut segments = path.trim_start_matches('/').splitn(2, '/');
+    let db_name = segments.next().unwrap_or("").to_owned();
+    let rest = segments.next().unwrap_or("");
+
+    if rest.is_empty() {
+        return match method {
+            "GET" if state.databases.contains_key(&db_name) => {
+                (200, json!({ "db_name": db_name }))
+            }
+            "PUT" if state.databases.contains_key(&db_name) => {
+                (412, json!({ "error": "file_exists" }))
+            }
+            "PUT" => {
+                state.databases.insert(db_name, BTreeMap::new());
+                (201, json!({ "ok": true }))
+            }
+            _ => not_found(),
+        };
+    }
+
+    if rest == "_bulk_docs" && method == "POST" {
+        state.bulk_requests += 1;
+    }
+
+    let docs = match state.databases.get_mut(&db_name) {
+        Some(docs) => docs,
+        None => return not_found(),
+    };
+
+    match (method, rest) {
+        ("POST", "_bulk_docs") => {
+            let results: Vec<Value> = body["docs"]
+                .as_array()
+                .map(|v| v.as_slice())
+                .unwrap_or(&[])
+                .iter()
+                .map(|doc| write_doc(docs, doc.clone()))
+                .collect();
+            (201, Value::Array(results))
+        }
+        ("GET", "_all_docs") => {
+            let params: BTreeMap<String, String> = query
+                .split('&')
+                .filter_map(|param| {
+                    let mut parts = param.splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(name), Some(value)) => Some((name.to_owned(), percent_decode(value))),
+                        _ => None,
+                    }
+                })
+                .collect();
+            let include_docs = params.get("include_docs").map_or(false, |v| v == "true");
+            let limit = params
+                .get("limit")
+                .and_then(|limit| usize::from_str(limit).ok())
+                .unwrap_or(usize::MAX);
+            let start_key = params
+                .get("startkey")
+                .and_then(|key| serde_json::from_str::<String>(key).ok())
+                .unwrap_or_default();
+            let end_key = params
+                .get("endkey")
+                .and_then(|key| serde_json::from_str::<String>(key).ok());
+
+            let rows: Vec<Value> = docs.range(start_key..)
+                .take_while(|&(id, _)| end_key.as_ref().map_or(true, |end_key| id <= end_key))
+                .take(limit)
+                .map(|(id, doc)| {
+                    let mut row = json!({ "id": id, "key": id, "value": { "rev": doc["_rev"] } });
+                    if include_docs {
+                        row["doc"] = doc.clone();
+                    }
+                    row
+                })
+                .collect();
+            (200, json!({ "total_rows": rows.len(), "offset": 0, "rows": rows }))
+        }
+        ("GET", id) => match docs.get(&percent_decode(id)) {
+            Some(doc) => (200, doc.clone()),
+            None => not_found(),
+        },
+        _ => not_found(),
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut chars = input.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = chars.by_ref().take(2).collect();
+                let hex = String::from_utf8_lossy(&hex).into_owned();
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap_or(b'?'));
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn not_found() -> (u16, Value) {
+    (404, json!({ "error": "not_found", "reason": "missing" }))
+}
+
+/// Store a document in the same manner as CouchDB, checking revisions and generating a new one.
+fn write_doc(docs: &mut BTreeMap<String, Value>, mut doc: Value) -> Value {
+    let id = match doc["_id"].as_str() {
+        Some(id) => id.to_owned(),
+        None => return json!({ "error": "bad_request", "reason": "missing _id" }),
+    };
+
+    let current_rev = docs.get(&id).map(|existing| existing["_rev"].clone());
+    if current_rev.as_ref() != doc.get("_rev") {
+        return json!({ "id": id, "error": "conflict", "reason": "Document update conflict." });
+    }
+
+    let generation = current_rev
+        .as_ref()
+        .and_then(|rev| rev.as_str())
+        .and_then(|rev| rev.split('-').next())
+        .and_then(|generation| u32::from_str(generation).ok())
+        .unwrap_or(0) + 1;
+    let rev = format!("{}-test", generation);
+
+    if doc["_deleted"] == true {
+        docs.remove(&id);
+    } else {
+        doc["_rev"] = Value::String(rev.clone());
+        docs.insert(id.clone(), doc);
+    }
+
+    json!({ "ok": true, "id": id, "rev": rev })
+}
@@ -0,0 +1,188 @@
+use failure::Error;
+use jmdict::Entry;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use couch::Database;
+
+/// The prefix for the IDs of the per-language design documents, e.g. _design/lang-eng.
+const DESIGN_DOC_PREFIX: &str = "_design/lang-";
+
+/// Statements that skip entries without senses in the given language, which replace $HAS_LANG in
+/// the map functions below. Documents split by language list their languages instead of including
+/// their senses.
+const HAS_LANG: &str = r#"var hasLang = doc.languages ? doc.languages.indexOf($LANG) !== -1 :
+    (doc.senses || []).some(function (sense) {
+      return (sense.lang || 'eng') === $LANG;
+    });
+  if (!hasLang) {
+    return;
+  }"#;
+
+/// Map function that indexes each kanji of entries that have senses in the given language.
+///
+/// In each of the map functions $LANG is replaced with the language code.
+const BY_KANJI: &str = r#"function (doc) {
+  $HAS_LANG
+  (doc.kanji_entries || []).forEach(function (k_ele) {
+    emit(k_ele.kanji, doc.priority_score || 0);
+  });
+}"#;
+
+/// Map function that indexes each reading of entries that have senses in the given language.
+const BY_READING: &str = r#"function (doc) {
+  $HAS_LANG
+  (doc.reading_entries || []).forEach(function (r_ele) {
+    emit(r_ele.kana, doc.priority_score || 0);
+  });
+}"#;
+
+/// Map function that indexes the hiragana and romaji search keys of entries that have senses in
+/// the given language.
+const BY_SEARCH_KEY: &str = r#"function (doc) {
+  $HAS_LANG
+  (doc.search_keys || []).forEach(function (key) {
+    emit(key, doc.priority_score || 0);
+  });
+}"#;
+
+/// Map function that indexes each (lowercased) word of the glosses in the given language.
+const BY_GLOSS: &str = r#"function (doc) {
+  var seen = {};
+  (doc.senses || []).forEach(function (sense) {
+    if ((sense.lang || doc.lang || 'eng') !== $LANG) {
+      return;
+    }
+    (sense.glosses || []).forEach(function (gloss) {
+      gloss.text.toLowerCase().split(/[\s.,;:!?()\[\]"\/]+/).forEach(function (word) {
+        if (word && !seen[word]) {
+          seen[word] = true;
+          emit(word, doc.priority_score || 0);
+        }
+      });
+    });
+  });
+}"#;
+
+/// The set of languages used by the senses of the supplied entries.
+///
+/// Senses without a language are English.
+pub fn languages(entries: &[Entry]) -> BTreeSet<String> {
+    entries
+        .iter()
+        .flat_map(|entry| entry.senses.iter())
+        .map(|sense| sense.language().to_owned())
+        .collect()
+}
+
+/// Produce the design document containing the by_kanji, by_reading, by_search_key and by_gloss
+/// views for the given language.
+///
+/// Each view emits the entry's priority score as its value so that clients can rank results.
+pub fn design_doc(lang: &str) -> Value {
+    let lang_literal = json!(lang).to_string();
+    let map_function = |template: &str| {
+        let map = template
+            .replace("$HAS_LANG", HAS_LANG)
+            .replace("$LANG", &lang_literal);
+        json!({ "map": map })
+    };
+
+    json!({
+        "_id": format!("{}{}", DESIGN_DOC_PREFIX, lang),
+        "language": "javascript",
+        "views": {
+            "by_kanji": map_function(BY_KANJI),
+            "by_reading": map_function(BY_READING),
+            "by_search_key": map_function(BY_SEARCH_KEY),
+            "by_gloss": map_function(BY_GLOSS),
+        },
+    })
+}
+
+/// Make the per-language design documents match the languages present in `entries`, returning
+/// the number of design documents written or deleted.
+pub fn sync_design_docs(db: &Database, entries: &[Entry]) -> Result<usize, Error> {
+    let languages = languages(entries);
+    let mut changes = 0;
+
+    for lang in &languages {
+        if db.update_doc(design_doc(lang))? {
+            changes += 1;
+        }
+    }
+
+    let stale: Vec<Value> = db.revs_with_prefix(DESIGN_DOC_PREFIX)?
+        .into_iter()
+        .filter(|(id, _)| !languages.contains(&id[DESIGN_DOC_PREFIX.len()..]))
+        .map(|(id, rev)| json!({ "_id": id, "_rev": rev, "_deleted": true }))
+        .collect();
+    if !stale.is_empty() {
+        db.bulk_docs(&stale)?;
+        changes += stale.len();
+    }
+
+    Ok(changes)
+}
+
+#[test]
+fn test_design_doc() {
+    let doc = design_doc("ger");
+    assert_eq!(doc["_id"], "_design/lang-ger");
+    let has_lang = [
+        "doc.languages.indexOf(\"ger\") !== -1",
+        "return (sense.lang || 'eng') === \"ger\";",
+    ];
+    let views: [(&str, &[&str]); 4] = [
+        ("by_kanji", &has_lang),
+        ("by_reading", &has_lang),
+        ("by_search_key", &has_lang),
+        ("by_gloss", &["if ((sense.lang || doc.lang || 'eng') !== \"ger\") {"]),
+    ];
+    for &(view, tests) in &views {
+        let map = doc["views"][view]["map"].as_str().unwrap();
+        for test in tests {
+            assert!(map.contains(test), "{} does not contain {}", view, test);
+        }
+        assert!(!map.contains("$LANG") && !map.contains("$HAS_LANG"));
+    }
+    assert!(!doc["views"]["by_gloss"]["map"]
+        .as_str()
+        .unwrap()
+        .contains("hasLang"));
+}
+
+#[test]
+fn test_sync_design_docs() {
+    use jmdict::EntryReader;
+    use test_server::TestServer;
+
+    let server = TestServer::start();
+    let db = Database::new(&server.url, "jmdict");
+    db.create_if_missing().unwrap();
+
+    let entries: Vec<Entry> = EntryReader::from_file("data/sample.xml")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let languages = languages(&entries);
+    assert!(languages.contains("eng"));
+    assert!(languages.contains("ger"));
+    assert!(languages.contains("rus"));
+
+    assert_eq!(sync_design_docs(&db, &entries).unwrap(), languages.len());
+    assert!(server.doc("jmdict", "_design/lang-eng").is_some());
+    assert_eq!(sync_design_docs(&db, &entries).unwrap(), 0);
+
+    // Dropping all but the English senses should remove the other design docs
+    let english: Vec<Entry> = entries
+        .into_iter()
+        .map(|mut entry| {
+            entry.senses.retain(|sense| sense.lang.is_none());
+            entry
+        })
+        .collect();
+    assert_eq!(sync_design_docs(&db, &english).unwrap(), languages.len() - 1);
+    assert!(server.doc("jmdict", "_design/lang-eng").is_some());
+    assert!(server.doc("jmdict", "_design/lang-ger").is_none());
+}
@@ -0,0 +1,261 @@
+//! Resolution of xref and ant elements to the entries they refer to.
+//!
+//! JMDict cross-references only give the kanji and/or reading of the target entry (and optionally
+//! a sense number) so they can only be resolved once all entries have been parsed.
+
+use diagnostic::{Diagnostic, DiagnosticKind};
+use entry::{CrossReference, Entry};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A cross-reference or antonym that could not be resolved to a single entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XrefProblem {
+    /// The ent_seq of the entry containing the reference
+    pub entry: u32,
+    /// True if the reference is an ant rather than an xref
+    pub antonym: bool,
+    pub reference: CrossReference,
+    pub kind: XrefProblemKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum XrefProblemKind {
+    /// No entry has the referenced kanji or reading
+    Dangling,
+    /// Several entries match the reference. Contains their ent_seq values.
+    Ambiguous(Vec<u32>),
+    /// The reference resolved to an entry but the sense number exceeds its number of senses
+    InvalidSense { target: u32, senses: usize },
+}
+
+impl XrefProblem {
+    /// A description of the problem that leaves out the entry containing the reference.
+    fn message(&self) -> String {
+        let element = if self.antonym { "ant" } else { "xref" };
+        match self.kind {
+            XrefProblemKind::Dangling => format!("Dangling {} \"{}\"", element, self.reference),
+            XrefProblemKind::Ambiguous(ref candidates) => {
+                let candidates: Vec<String> = candidates.iter().map(u32::to_string).collect();
+                format!(
+                    "Ambiguous {} \"{}\" (matches {})",
+                    element,
+                    self.reference,
+                    candidates.join(", ")
+                )
+            }
+            XrefProblemKind::InvalidSense { target, senses } => format!(
+                "Invalid sense in {} \"{}\" (entry {} has {} senses)",
+                element, self.reference, target, senses
+            ),
+        }
+    }
+}
+
+impl fmt::Display for XrefProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in entry {}", self.message(), self.entry)
+    }
+}
+
+impl From<XrefProblem> for Diagnostic {
+    fn from(problem: XrefProblem) -> Diagnostic {
+        let kind = DiagnosticKind::UnresolvedCrossReference;
+        Diagnostic {
+            severity: kind.severity(),
+            kind,
+            message: problem.message(),
+            entry: Some(problem.entry),
+            position: None,
+        }
+    }
+}
+
+/// Maps each kanji and reading to the indices of the entries that contain it.
+struct Index<'a> {
+    entries: &'a [Entry],
+    by_key: HashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> Index<'a> {
+    fn new(entries: &'a [Entry]) -> Index<'a> {
+        let mut by_key: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let kanji = entry.kanji_entries.iter().map(|k_ele| k_ele.kanji.as_str());
+            let readings = entry.reading_entries.iter().map(|r_ele| r_ele.kana.as_str());
+            for key in kanji.chain(readings) {
+                let indices = by_key.entry(key).or_default();
+                if indices.last() != Some(&i) {
+                    indices.push(i);
+                }
+            }
+        }
+        Index { entries, by_key }
+    }
+
+    /// Look up the entry referred to by `reference` from the entry at index `source`.
+    #[allow(clippy::unnecessary_map_or)]
+    fn resolve(&self, source: usize, reference: &CrossReference) -> Result<u32, XrefProblemKind> {
+        let candidates: Vec<&Entry> = self.by_key
+            .get(reference.kanji_or_reading.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+            .iter()
+            // An entry never refers to itself
+            .filter(|&&i| i != source)
+            .map(|&i| &self.entries[i])
+            .filter(|entry| {
+                reference.reading.as_ref().map_or(true, |reading| {
+                    entry
+                        .reading_entries
+                        .iter()
+                        .any(|r_ele| &r_ele.kana == reading)
+                })
+            })
+            .collect();
+
+        let target = match candidates.len() {
+            0 => return Err(XrefProblemKind::Dangling),
+            1 => candidates[0],
+            _ => {
+                return Err(XrefProblemKind::Ambiguous(
+                    candidates.iter().map(|entry| entry.id).collect(),
+                ))
+            }
+        };
+
+        // Sense numbers count the English senses. If those have been filtered out we have nothing
+        // to check against.
+        let senses = target
+            .senses
+            .iter()
+            .filter(|sense| sense.language() == "eng")
+            .count();
+        if let Some(sense_index) = reference.sense_index {
+            if senses > 0 && (sense_index == 0 || usize::from(sense_index) > senses) {
+                return Err(XrefProblemKind::InvalidSense {
+                    target: target.id,
+                    senses,
+                });
+            }
+        }
+
+        Ok(target.id)
+    }
+}
+
+/// Set the `target` of every xref and ant in `entries` that refers to exactly one entry, returning
+/// the references that could not be resolved.
+pub fn resolve_cross_refs(entries: &mut [Entry]) -> Vec<XrefProblem> {
+    let mut targets: Vec<Option<u32>> = Vec::new();
+    let mut problems: Vec<XrefProblem> = Vec::new();
+
+    {
+        let index = Index::new(entries);
+        for (i, entry) in entries.iter().enumerate() {
+            for sense in &entry.senses {
+                let references = sense
+                    .cross_refs
+                    .iter()
+                    .map(|reference| (reference, false))
+                    .chain(sense.antonyms.iter().map(|reference| (reference, true)));
+                for (reference, antonym) in references {
+                    match index.resolve(i, reference) {
+                        Ok(target) => targets.push(Some(target)),
+                        Err(kind) => {
+                            targets.push(None);
+                            problems.push(XrefProblem {
+                                entry: entry.id,
+                                antonym,
+                                reference: reference.clone(),
+                                kind,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Apply the targets in the same order we visited the references above
+    let mut targets = targets.into_iter();
+    for sense in entries.iter_mut().flat_map(|entry| entry.senses.iter_mut()) {
+        let references = sense.cross_refs.iter_mut().chain(sense.antonyms.iter_mut());
+        for reference in references {
+            reference.target = targets.next().unwrap();
+        }
+    }
+
+    problems
+}
+
+#[test]
+fn test_resolve_cross_refs() {
+    use parser::EntryReader;
+
+    let xml = r#"<JMdict>
+<entry><ent_seq>1</ent_seq>
+<k_ele><keb>何方</keb></k_ele>
+<r_ele><reb>どちら</reb></r_ele><r_ele><reb>どなた</reb></r_ele>
+<sense><gloss>which way</gloss></sense>
+<sense><gloss>who</gloss></sense>
+<sense><gloss xml:lang="ger">wer</gloss></sense>
+</entry>
+<entry><ent_seq>2</ent_seq>
+<k_ele><keb>彼方</keb></k_ele>
+<r_ele><reb>あちら</reb></r_ele>
+<sense><gloss>that way</gloss></sense>
+</entry>
+<entry><ent_seq>3</ent_seq>
+<k_ele><keb>彼方</keb></k_ele>
+<r_ele><reb>かなた</reb></r_ele>
+<sense><gloss>yonder</gloss></sense>
+</entry>
+<entry><ent_seq>4</ent_seq>
+<r_ele><reb>こちら</reb></r_ele>
+<sense>
+<xref>何方・どちら・2</xref>
+<xref>何方・3</xref>
+<xref>彼方</xref>
+<xref>彼方・かなた</xref>
+<xref>此方</xref>
+<xref>こちら</xref>
+<ant>どなた</ant>
+<gloss>this way</gloss>
+</sense>
+</entry>
+</JMdict>"#;
+    let mut entries: Vec<Entry> = EntryReader::new(xml.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 4);
+
+    let problems = resolve_cross_refs(&mut entries);
+
+    let sense = &entries[3].senses[0];
+    let targets: Vec<Option<u32>> = sense.cross_refs.iter().map(|xref| xref.target).collect();
+    assert_eq!(targets, [Some(1), None, None, Some(3), None, None]);
+    assert_eq!(sense.antonyms[0].target, Some(1));
+
+    let kinds: Vec<&XrefProblemKind> = problems.iter().map(|problem| &problem.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            &XrefProblemKind::InvalidSense {
+                target: 1,
+                senses: 2,
+            },
+            &XrefProblemKind::Ambiguous(vec![2, 3]),
+            &XrefProblemKind::Dangling,
+            &XrefProblemKind::Dangling,
+        ]
+    );
+    assert!(problems.iter().all(|problem| problem.entry == 4 && !problem.antonym));
+    assert_eq!(
+        problems[1].to_string(),
+        "Ambiguous xref \"彼方\" (matches 2, 3) in entry 4"
+    );
+    let diagnostic = Diagnostic::from(problems[1].clone());
+    assert_eq!(diagnostic.to_string(), problems[1].to_string());
+    assert_eq!(diagnostic.kind, DiagnosticKind::UnresolvedCrossReference);
+}
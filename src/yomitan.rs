@@ -0,0 +1,317 @@
+//! Export of entries as a Yomitan (formerly Yomichan) dictionary archive.
+//!
+//! The archive is a zip file containing:
+//!
+//! * `index.json`, describing the dictionary and release.
+//! * `term_bank_N.json`, with a term for each sense in the chosen language of each kanji/reading
+//!   combination that the re_restr, stagk and stagr restrictions allow. Kana-only words are
+//!   written with an empty reading. The terms of an entry share its ent_seq as their sequence
+//!   number so that Yomitan can group them.
+//! * `tag_bank_1.json`, describing the tags used by the terms. Tags are the entity codes (e.g.
+//!   "v5k") and use the entity descriptions from the DTD as their notes.
+//!
+//! JMdict only gives the part of speech for the first of a run of senses that share it so each
+//! sense without a part of speech uses that of the sense before it in the same language.
+
+use failure::{Error, ResultExt};
+use jmdict::dtd::EntityTable;
+use jmdict::entities::{Entity, PartOfSpeech};
+use jmdict::{Entry, KanjiEntry, ReadingEntry, ReleaseInfo, Sense};
+use serde_json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{Seek, Write};
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+/// The number of terms in each term bank file.
+const TERMS_PER_BANK: usize = 10_000;
+
+/// The tag Yomitan uses to mark terms with a common priority, i.e. the (P) marker in EDICT.
+const POPULAR_TAG: &str = "P";
+
+/// Write the entries to `out` as a Yomitan dictionary archive using the senses in `lang` (e.g.
+/// "eng"), returning the number of terms written.
+pub fn export_entries<W: Write + Seek>(
+    entries: &[Entry],
+    entities: &EntityTable,
+    lang: &str,
+    release: &ReleaseInfo,
+    out: W,
+) -> Result<usize, Error> {
+    let mut tags = TagBank::default();
+    let mut terms: Vec<Value> = Vec::new();
+    for entry in entries {
+        terms.extend(entry_terms(entry, lang, &mut tags));
+    }
+
+    let mut zip = ZipWriter::new(out);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("index.json", options)?;
+    serde_json::to_writer(&mut zip, &index(lang, release)).context("Failed to write index")?;
+
+    for (i, bank) in terms.chunks(TERMS_PER_BANK).enumerate() {
+        zip.start_file(format!("term_bank_{}.json", i + 1), options)?;
+        serde_json::to_writer(&mut zip, bank).context("Failed to write term bank")?;
+    }
+
+    zip.start_file("tag_bank_1.json", options)?;
+    serde_json::to_writer(&mut zip, &tags.to_json(entities)).context("Failed to write tags")?;
+
+    zip.finish()?;
+    Ok(terms.len())
+}
+
+/// The dictionary description, using the creation date of the release as the revision so that
+/// Yomitan can tell when an update is available. Yomitan identifies dictionaries by their title so
+/// the languages other than English are included in it.
+fn index(lang: &str, release: &ReleaseInfo) -> Value {
+    let revision = match release.created {
+        Some(ref created) => created.clone(),
+        None => release.input_hash.chars().take(12).collect(),
+    };
+    let title = match lang {
+        "eng" => "JMdict".to_owned(),
+        _ => format!("JMdict ({})", lang),
+    };
+    json!({
+        "title": title,
+        "revision": format!("jmdict-{}", revision),
+        "format": 3,
+        "sequenced": true,
+        "author": "Electronic Dictionary Research and Development Group",
+        "url": "https://www.edrdg.org/jmdict/j_jmdict.html",
+        "description": "Japanese-Multilingual Dictionary",
+        "attribution": "This dictionary uses the JMdict dictionary files. These files are the \
+                        property of the Electronic Dictionary Research and Development Group, and \
+                        are used in conformance with the Group's licence.",
+    })
+}
+
+/// The terms for each combination of kanji, reading and sense in `lang` that applies to them.
+#[allow(clippy::unnecessary_map_or)]
+fn entry_terms(entry: &Entry, lang: &str, tags: &mut TagBank) -> Vec<Value> {
+    let mut forms: Vec<(Option<&KanjiEntry>, &ReadingEntry)> = Vec::new();
+    for k_ele in &entry.kanji_entries {
+        for r_ele in &entry.reading_entries {
+            if !r_ele.no_kanji
+                && (r_ele.related_kanji.is_empty() || r_ele.related_kanji.contains(&k_ele.kanji))
+            {
+                forms.push((Some(k_ele), r_ele));
+            }
+        }
+    }
+    for r_ele in &entry.reading_entries {
+        if r_ele.no_kanji || entry.kanji_entries.is_empty() {
+            forms.push((None, r_ele));
+        }
+    }
+
+    let mut senses: Vec<(&Sense, &[PartOfSpeech])> = Vec::new();
+    let mut part_of_speech: &[PartOfSpeech] = &[];
+    for sense in entry.senses.iter().filter(|sense| sense.language() == lang) {
+        if !sense.part_of_speech.is_empty() {
+            part_of_speech = &sense.part_of_speech;
+        }
+        if !sense.glosses.is_empty() {
+            senses.push((sense, part_of_speech));
+        }
+    }
+
+    let mut terms = Vec::new();
+    for (k_ele, r_ele) in forms {
+        let (expression, reading) = match k_ele {
+            Some(k_ele) => (k_ele.kanji.as_str(), r_ele.kana.as_str()),
+            None => (r_ele.kana.as_str(), ""),
+        };
+
+        let mut term_tags = tags.add(&r_ele.info, "form");
+        let mut score = r_ele.priority_score();
+        let mut popular = r_ele.priority.iter().any(|priority| priority.is_common());
+        if let Some(k_ele) = k_ele {
+            term_tags.extend(tags.add(&k_ele.info, "form"));
+            score = score.max(k_ele.priority_score());
+            popular |= k_ele.priority.iter().any(|priority| priority.is_common());
+        }
+        if popular {
+            tags.categories.insert(POPULAR_TAG, "popular");
+            term_tags.push(POPULAR_TAG);
+        }
+
+        for &(sense, part_of_speech) in &senses {
+            let kanji_applies = sense.only_kanji.is_empty()
+                || k_ele.map_or(false, |k_ele| sense.only_kanji.contains(&k_ele.kanji));
+            let reading_applies =
+                sense.only_readings.is_empty() || sense.only_readings.contains(&r_ele.kana);
+            if !kanji_applies || !reading_applies {
+                continue;
+            }
+
+            let mut definition_tags = tags.add(part_of_speech, "partOfSpeech");
+            definition_tags.extend(tags.add(&sense.misc, "misc"));
+            definition_tags.extend(tags.add(&sense.field, "field"));
+            definition_tags.extend(tags.add(&sense.dialect, "dialect"));
+
+            let mut rules: Vec<&str> = Vec::new();
+            for rule in part_of_speech.iter().filter_map(deinflection_rule) {
+                if !rules.contains(&rule) {
+                    rules.push(rule);
+                }
+            }
+
+            let glossary: Vec<&str> = sense.glosses.iter().map(|g| g.text.as_str()).collect();
+            terms.push(json!([
+                expression,
+                reading,
+                definition_tags.join(" "),
+                rules.join(" "),
+                score,
+                glossary,
+                entry.id,
+                term_tags.join(" "),
+            ]));
+        }
+    }
+
+    terms
+}
+
+/// The Yomitan deinflection rule for words with this part of speech, if any.
+fn deinflection_rule(part_of_speech: &PartOfSpeech) -> Option<&'static str> {
+    match part_of_speech.code() {
+        "v1" | "v1-s" => Some("v1"),
+        "vk" => Some("vk"),
+        "vs-s" | "vs-i" => Some("vs"),
+        "vz" => Some("vz"),
+        "adj-i" | "adj-ix" => Some("adj-i"),
+        code if code.starts_with("v5") => Some("v5"),
+        _ => None,
+    }
+}
+
+/// The tags used by the terms, keyed by name, with their category.
+#[derive(Default)]
+struct TagBank {
+    categories: BTreeMap<&'static str, &'static str>,
+}
+
+impl TagBank {
+    /// Record the tags for `values` under `category`, returning their names.
+    fn add<T: Entity>(&mut self, values: &[T], category: &'static str) -> Vec<&'static str> {
+        let names: Vec<&'static str> = values.iter().map(Entity::code).collect();
+        for name in &names {
+            self.categories.entry(name).or_insert(category);
+        }
+        names
+    }
+
+    /// The tag bank as Yomitan expects, i.e. [name, category, order, notes, score] for each tag.
+    fn to_json(&self, entities: &EntityTable) -> Value {
+        let tags: Vec<Value> = self.categories
+            .iter()
+            .map(|(&name, &category)| {
+                if name == POPULAR_TAG {
+                    return json!([name, category, -10, "popular term", 10]);
+                }
+                let notes = entities.get(name).map_or("", String::as_str);
+                json!([name, category, 0, notes, 0])
+            })
+            .collect();
+        Value::Array(tags)
+    }
+}
+
+#[test]
+fn test_export_entries() {
+    use jmdict::EntryReader;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    let mut reader = EntryReader::from_file("data/sample.xml").unwrap();
+    let entries = reader
+        .by_ref()
+        .collect::<Result<Vec<Entry>, Error>>()
+        .unwrap();
+    let mut out = Cursor::new(Vec::new());
+    let release = reader.release_info();
+    let count = export_entries(&entries, reader.entities(), "eng", &release, &mut out).unwrap();
+
+    let mut zip = ZipArchive::new(out).unwrap();
+    let mut read_json = |name: &str| -> Value {
+        let mut json = String::new();
+        zip.by_name(name).unwrap().read_to_string(&mut json).unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+
+    let index = read_json("index.json");
+    assert_eq!(index["title"], "JMdict");
+    assert_eq!(index["revision"], "jmdict-2018-02-14");
+    assert_eq!(index["sequenced"], true);
+
+    let terms = read_json("term_bank_1.json");
+    let terms = terms.as_array().unwrap();
+    assert_eq!(terms.len(), count);
+    let terms_for = |expression: &str| -> Vec<&Value> {
+        terms.iter().filter(|term| term[0] == expression).collect()
+    };
+
+    // Only the English glosses are written
+    let english: Vec<&str> = entries
+        .iter()
+        .flat_map(|entry| &entry.senses)
+        .filter(|sense| sense.language() == "eng")
+        .flat_map(|sense| sense.glosses.iter().map(|gloss| gloss.text.as_str()))
+        .collect();
+    assert!(terms
+        .iter()
+        .flat_map(|term| term[5].as_array().unwrap())
+        .all(|gloss| english.contains(&gloss.as_str().unwrap())));
+    assert_eq!(terms_for("ＣＤプレーヤー").len(), 1);
+
+    // Kana-only words have an empty reading
+    assert_eq!(terms_for("ヽ")[0][1], "");
+
+    // re_restr limits the readings paired with each kanji
+    let player = terms_for("ＣＤプレーヤー");
+    assert!(player.iter().all(|term| term[1] == "シーディープレーヤー"));
+    assert_eq!(player[0][2], "n");
+    assert_eq!(player[0][5], json!(["CD player"]));
+    assert_eq!(player[0][6], 1_000_110);
+    assert_eq!(player[0][7], "P");
+    assert_eq!(player[0][4], 40);
+
+    // stagk limits the senses for each kanji and the part of speech carries over to later senses
+    let postpone = json!(["to postpone", "to extend"]);
+    let nobe = terms_for("延べる");
+    assert_eq!(nobe[0][2], "v1 vt");
+    assert_eq!(nobe[0][3], "v1");
+    assert_eq!(nobe[1][5], postpone);
+    assert_eq!(nobe[1][3], "v1");
+    assert!(terms_for("展べる").iter().all(|term| term[5] != postpone));
+
+    let tags = read_json("tag_bank_1.json");
+    let tags = tags.as_array().unwrap();
+    assert!(tags.contains(&json!(["v1", "partOfSpeech", 0, "Ichidan verb", 0])));
+    assert!(tags.contains(&json!(["P", "popular", -10, "popular term", 10])));
+
+    // Senses in other languages don't take the part of speech of the English senses
+    let mut out = Cursor::new(Vec::new());
+    export_entries(&entries, reader.entities(), "ger", &release, &mut out).unwrap();
+    let mut zip = ZipArchive::new(out).unwrap();
+    let mut json = String::new();
+    zip.by_name("term_bank_1.json")
+        .unwrap()
+        .read_to_string(&mut json)
+        .unwrap();
+    let terms: Value = serde_json::from_str(&json).unwrap();
+    let player: Vec<&Value> = terms
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|term| term[0] == "ＣＤプレーヤー")
+        .collect();
+    assert_eq!(player.len(), 1);
+    assert_eq!(player[0][5], json!(["(m) CD-Spieler", "(m) CD-Player"]));
+    assert_eq!(player[0][2], "");
+}